@@ -1,4 +1,3 @@
-use std::collections::HashMap;
 use std::error::Error;
 use std::ffi::OsStr;
 use std::fmt::{Debug, Display, Formatter};
@@ -9,8 +8,9 @@ use image::ImageReader;
 use image::codecs::png::PngEncoder;
 use metaphor_apk_rs::read::ApkReader;
 use walkdir::WalkDir;
-use ssbp6_lib::cell::Cell;
-use ssbp6_lib::project::ProjectHeader;
+use ssbp6_lib::model::Project;
+use ssbp6_lib::reduce::KeyframeTolerances;
+use ssbp6_lib::project::SsbpFile;
 use rayon::prelude::*;
 
 #[derive(Debug)]
@@ -134,25 +134,17 @@ fn read_file<P: AsRef<Path>>(parent: P, filename: P, locale: P, tex: P, output:
     println!("{:?}", parent.as_ref().join(filename.as_ref()));
     let binary = std::fs::read(parent.as_ref().join(filename.as_ref()))?;
     // println!("{} bytes", binary.len());
-    let header = unsafe { &*(binary.as_ptr().add(0) as *const ProjectHeader) };
-    // println!("{:?}", header);
-    let mut cells: HashMap<u16, Cell> = HashMap::new();
-    // cell array index -> (map index, cell name) (for getting reference in ssae)
-    let mut cell_resolver_anime: HashMap<usize, (u16, &str)> = HashMap::new();
-    for (i, entry) in header.get_cells(&binary).iter().enumerate() {
-        let map = entry.get_cell_map(&binary);
-        match cells.get_mut(&map.get_index()) {
-            Some(cell) => cell.add(entry),
-            None => { let _ = cells.insert(map.get_index(), Cell::new(map, entry)); }
-        };
-        cell_resolver_anime.insert(i, (map.get_index(), entry.get_name(&binary)));
+    let mut project = Project::from_file(&SsbpFile::parse(&binary)?)?;
+    for warning in &project.warnings {
+        println!("WARNING in {}: {}", filename.as_ref().display(), warning);
     }
-    let mut cell_names = Vec::with_capacity(cells.len());
-    for i in 0..cells.len() {
-        cell_names.push(String::new());
-    }
-    for (i, cell) in &cells {
-        let val = cell.to_xml(&binary, |image_path|  {
+    let tolerances = KeyframeTolerances::default();
+    project.fit_curves(&tolerances);
+    project.reduce_keyframes(&tolerances);
+    // println!("{:?}", project);
+    let mut cell_names = Vec::with_capacity(project.cell_maps.len());
+    for cell_map in &project.cell_maps {
+        let val = cell_map.to_xml(|image_path|  {
                 let (base, ext) = image_path.rsplit_once(".").unwrap();
                 let (name, is_apk) = match ext {
                     "apk" => (format!("{}.png", base), true),
@@ -196,24 +188,29 @@ fn read_file<P: AsRef<Path>>(parent: P, filename: P, locale: P, tex: P, output:
                 };
                 Ok((name, dims.into()))
         })?;
-        cell_names[*i as usize] = format!("{}.ssce", cell.get_name(&binary));
-        // cell_names.push(format!("{}.ssce", cell.get_name(&binary)));
-        std::fs::write(output.as_ref().join(&cell_names[*i as usize]), &val)?;
+        cell_names.push(format!("{}.ssce", cell_map.name));
+        std::fs::write(output.as_ref().join(cell_names.last().unwrap()), &val)?;
     }
-    let mut anime_names = Vec::with_capacity(header.get_num_anime() as usize);
-    for anime in header.get_anime(&binary) {
-        let val = anime.to_xml(&cell_names, &binary, &cell_resolver_anime)?;
-        anime_names.push(format!("{}.ssae", anime.get_name(&binary)));
+    let mut anime_names = Vec::with_capacity(project.anime_packs.len());
+    for anime in &project.anime_packs {
+        let val = anime.to_xml(&cell_names)?;
+        anime_names.push(format!("{}.ssae", anime.name));
         std::fs::write(output.as_ref().join(anime_names.last().unwrap()), &val)?;
     }
-    let mut effect_names = Vec::with_capacity(header.get_num_effects() as usize);
-    for effect in header.get_effects(&binary) {
-        let val = effect.to_xml(&binary, header.get_cells(&binary))?;
-        effect_names.push(format!("{}.ssee", effect.get_name(&binary)));
+    let mut effect_names = Vec::with_capacity(project.effects.len());
+    for effect in &project.effects {
+        let val = effect.to_xml()?;
+        effect_names.push(format!("{}.ssee", effect.name));
         std::fs::write(output.as_ref().join(effect_names.last().unwrap()), &val)?;
     }
+    let mut sequence_names = Vec::with_capacity(project.sequence_packs.len());
+    for pack in &project.sequence_packs {
+        let val = pack.to_xml()?;
+        sequence_names.push(format!("{}.ssqe", pack.name));
+        std::fs::write(output.as_ref().join(sequence_names.last().unwrap()), &val)?;
+    }
     let name = filename.as_ref().file_stem().unwrap().to_str().unwrap();
-    let proj_xml = header.to_xml(name, &cell_names, &anime_names, &effect_names)?;
+    let proj_xml = project.to_xml(name, &cell_names, &anime_names, &effect_names, &sequence_names)?;
     std::fs::write(output.as_ref().join(format!("{}.sspj", name)), proj_xml.as_slice())?;
     Ok(())
 }
\ No newline at end of file
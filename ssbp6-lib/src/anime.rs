@@ -14,15 +14,19 @@ use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::io::{Cursor, Seek, SeekFrom, Write};
 use bitflags::bitflags;
-use glam::{Vec2, Vec3};
+use glam::{UVec2, UVec3, Vec2, Vec3};
 use quick_xml::events::BytesText;
 use quick_xml::events::attributes::Attribute;
 use quick_xml::Writer;
-use crate::cell::CastError;
-use crate::util::{create_blank_element, create_name_list, to_xml_anime_bg_settings, to_xml_anime_settings, Ptr, StringPtr};
+use crate::cell::{CastError, InterpolateType};
+use crate::model;
+use crate::project::SsbpFile;
+use crate::revision::Layout;
+use crate::util::{check_enum, check_range, create_blank_element, create_name_list, to_xml_anime_bg_settings, to_xml_anime_settings, BinaryStruct, ParseError, Ptr, StringPtr, Table};
 
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(non_camel_case_types)]
 pub enum PartType {
     null,			//< Has no region, only SRT information. However, circular collision detection can be set.
@@ -41,6 +45,7 @@ pub enum PartType {
 
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(non_camel_case_types)]
 pub enum BlendType {
     mix,
@@ -57,7 +62,7 @@ impl TryFrom<u16> for BlendType {
     type Error = CastError;
     fn try_from(value: u16) -> Result<Self, Self::Error> {
         if value <= Self::invert as u16 {
-            Ok(unsafe { std::mem::transmute(value)})
+            Ok(unsafe { std::mem::transmute::<u16, Self>(value) })
         } else {
             Err(CastError::new("BlendType", value as usize))
         }
@@ -66,6 +71,7 @@ impl TryFrom<u16> for BlendType {
 
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(non_camel_case_types)]
 pub enum BoundsType {
     none,			//< Not used for collision detection.
@@ -95,6 +101,15 @@ const _: () = {
     ["Size of PartEntry"][size_of::<PartEntry>() - 0x20];
 };
 
+unsafe impl BinaryStruct for PartEntry {
+    const NAME: &'static str = "PartEntry";
+    fn validate(binary: &[u8], offset: usize) -> Result<(), ParseError> {
+        check_enum("PartType", binary, offset + std::mem::offset_of!(PartEntry, _type), PartType::bonepoint as u16)?;
+        check_enum("BoundsType", binary, offset + std::mem::offset_of!(PartEntry, bounds_type), BoundsType::circle_smax as u16)?;
+        check_enum("BlendType", binary, offset + std::mem::offset_of!(PartEntry, alpha_blend_type), BlendType::invert as u16)
+    }
+}
+
 #[derive(Debug)]
 pub enum PartError {
     MissingPathInRefName(String)
@@ -114,8 +129,8 @@ impl PartEntry {
     pub fn get_parent_index(&self) -> i16 {
         self.parent_index
     }
-    pub fn get_name(&self, binary: &[u8]) -> &str {
-        self.name.value(binary)
+    pub fn get_name<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.name.value(binary, "PartEntry.name")
     }
     pub fn get_type(&self) -> PartType {
         self._type
@@ -126,49 +141,71 @@ impl PartEntry {
     pub fn get_alpha_blend_type(&self) -> BlendType {
         self.alpha_blend_type
     }
-    pub fn get_ref_name(&self, binary: &[u8]) -> &str {
-        self.ref_name.value(binary)
+    pub fn get_ref_name<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.ref_name.value(binary, "PartEntry.ref_name")
     }
-    pub fn get_effect_name(&self, binary: &[u8]) -> &str {
-        self.effect_name.value(binary)
+    pub fn get_effect_name<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.effect_name.value(binary, "PartEntry.effect_name")
     }
-    pub fn get_color_label(&self, binary: &[u8]) -> &str {
-        self.color_label.value(binary)
+    pub fn get_color_label<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.color_label.value(binary, "PartEntry.color_label")
     }
 
-    pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>, binary: &[u8])
+    pub fn to_model(&self, binary: &[u8]) -> Result<model::Part, ParseError> {
+        Ok(model::Part {
+            name: self.get_name(binary)?.to_string(),
+            index: self.index,
+            parent_index: self.parent_index,
+            part_type: self._type,
+            bounds_type: self.bounds_type,
+            alpha_blend_type: self.alpha_blend_type,
+            ref_name: self.get_ref_name(binary)?.to_string(),
+            effect_name: self.get_effect_name(binary)?.to_string(),
+            color_label: self.get_color_label(binary)?.to_string(),
+            mask_influence: self.mask_influence,
+            bone: None,
+            inheritance: model::Inheritance::default(),
+        })
+    }
+}
+
+impl model::Part {
+    pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>, parent: Option<&model::Part>)
         -> std::io::Result<()> {
         writer.create_element("name")
-            .write_text_content(BytesText::new(self.name.value(binary)))?;
+            .write_text_content(BytesText::new(&self.name))?;
         writer.create_element("arrayIndex")
             .write_text_content(BytesText::new(&format!("{}", self.index)))?;
         writer.create_element("parentIndex")
             .write_text_content(BytesText::new(&format!("{}", self.parent_index)))?;
         writer.create_element("type")
-            .write_text_content(BytesText::new(&format!("{:?}", self._type)))?;
+            .write_text_content(BytesText::new(&format!("{:?}", self.part_type)))?;
         writer.create_element("boundsType")
             .write_text_content(BytesText::new(&format!("{:?}", self.bounds_type)))?;
+        // parts only need their own rates when they differ from the parent's
+        let inherit_parent = parent.is_some_and(|p| p.inheritance == self.inheritance);
         writer.create_element("inheritType")
-            .write_text_content(BytesText::new(match self.parent_index {
-                -1 => "self", _ => "parent"
+            .write_text_content(BytesText::new(match inherit_parent {
+                true => "parent", false => "self"
             }))?;
+        let flag = |v: bool| BytesText::new(match v { true => "1", false => "0" });
         writer.create_element("ineheritRates")
             .write_inner_content(|writer| {
                 writer.create_element("ALPH")
-                    .write_text_content(BytesText::new("1"))?;
+                    .write_text_content(flag(self.inheritance.opacity))?;
                 writer.create_element("IFLH")
-                    .write_text_content(BytesText::new("0"))?;
+                    .write_text_content(flag(self.inheritance.image_flip_h))?;
                 writer.create_element("IFLV")
-                    .write_text_content(BytesText::new("0"))?;
+                    .write_text_content(flag(self.inheritance.image_flip_v))?;
                 writer.create_element("FLPH")
-                    .write_text_content(BytesText::new("0"))?;
+                    .write_text_content(flag(self.inheritance.flip_h))?;
                 writer.create_element("FLPV")
-                    .write_text_content(BytesText::new("0"))?;
+                    .write_text_content(flag(self.inheritance.flip_v))?;
                 writer.create_element("HIDE")
-                    .write_text_content(BytesText::new("0"))?;
+                    .write_text_content(flag(self.inheritance.hide))?;
                 Ok(())
             })?;
-        let ref_anime_name = self.ref_name.value(binary);
+        let ref_anime_name = &self.ref_name;
         if !ref_anime_name.is_empty() {
             let (pack, anim) = ref_anime_name.split_once("/")
                 .ok_or(std::io::Error::other(PartError::MissingPathInRefName(ref_anime_name.to_string())))?;
@@ -177,7 +214,7 @@ impl PartEntry {
             writer.create_element("refAnime")
                 .write_text_content(BytesText::new(anim))?;
         }
-        let ref_effect_name = self.effect_name.value(binary);
+        let ref_effect_name = &self.effect_name;
         if !ref_effect_name.is_empty() {
             writer.create_element("refEffectName")
                 .write_text_content(BytesText::new(ref_effect_name))?;
@@ -192,11 +229,19 @@ impl PartEntry {
             .write_text_content(BytesText::new("0"))?;
         writer.create_element("expandChildren")
             .write_text_content(BytesText::new("1"))?;
-        let ref_color_name = self.color_label.value(binary);
+        let ref_color_name = &self.color_label;
         if !ref_color_name.is_empty() {
             writer.create_element("colorLabel")
                 .write_text_content(BytesText::new(ref_color_name))?;
         }
+        if let Some(bone) = &self.bone {
+            writer.create_element("boneLength")
+                .write_text_content(BytesText::new(&format!("{}", bone.length)))?;
+            writer.create_element("bonePosition")
+                .write_text_content(BytesText::new(&format!("{} {}", bone.position.x, bone.position.y)))?;
+            writer.create_element("boneRotation")
+                .write_text_content(BytesText::new(&format!("{}", bone.rotation)))?;
+        }
         create_blank_element(writer, "refCellTag")?;
         // writer.create_element("refCellTag")
         //     .write_text_content(BytesText::new(self.ref_name.value(binary)))?;
@@ -212,8 +257,8 @@ pub struct AnimEntry {
     name: StringPtr,
     default_data: Ptr<AnimInitial>,
     frame_data: Ptr<FrameData>,
-    user_data: u32,
-    label_data: Ptr<LabelEntry>,
+    user_data: Ptr<UserDataFrame>,
+    label_data: Ptr<Ptr<LabelEntry>>,
     mesh_data_uv: Ptr<MeshUV>,
     mesh_data_indices: Ptr<MeshIndex>,
     start_frames: u16,
@@ -231,17 +276,34 @@ const _: () = {
     ["Size of AnimEntry"][size_of::<AnimEntry>() - 0x34];
 };
 
+unsafe impl BinaryStruct for AnimEntry {
+    const NAME: &'static str = "AnimEntry";
+}
+
 impl AnimEntry {
-    pub fn get_default_data(&self, binary: &[u8], num_parts: usize) -> &[AnimInitial] {
-        self.default_data.array(binary, num_parts)
+    pub fn get_default_data<'a>(&self, binary: &'a [u8], num_parts: usize, layout: &Layout)
+        -> Result<Table<'a, AnimInitial>, ParseError> {
+        self.default_data.table(binary, num_parts, layout.anim_initial_size)
     }
-    pub fn get_frame_data(&self, binary: &[u8], num_frames: usize) -> &[FrameData] {
+    pub fn get_frame_data<'a>(&self, binary: &'a [u8], num_frames: usize) -> Result<&'a [FrameData], ParseError> {
         self.frame_data.array(binary, num_frames)
     }
-    pub fn get_mesh_uv(&self, binary: &[u8], num_parts: usize) -> &[MeshUV] {
+    /// Decode the state of every part on each frame in turn
+    pub fn get_part_frames<'a>(&self, binary: &'a [u8], num_parts: usize, layout: &Layout)
+        -> Result<FrameIterator<'a>, ParseError> {
+        Ok(FrameIterator {
+            binary,
+            frames: self.get_frame_data(binary, self.total_frames as usize)?,
+            initial: self.get_default_data(binary, num_parts, layout)?,
+            frame: 0,
+            entry: 0,
+            data: None,
+        })
+    }
+    pub fn get_mesh_uv<'a>(&self, binary: &'a [u8], num_parts: usize) -> Result<&'a [MeshUV], ParseError> {
         self.mesh_data_uv.array(binary, num_parts)
     }
-    pub fn get_mesh_index(&self, binary: &[u8], num_parts: usize) -> &[MeshIndex] {
+    pub fn get_mesh_index<'a>(&self, binary: &'a [u8], num_parts: usize) -> Result<&'a [MeshIndex], ParseError> {
         self.mesh_data_indices.array(binary, num_parts)
     }
     pub fn get_start_frames(&self) -> u16 {
@@ -253,308 +315,353 @@ impl AnimEntry {
     pub fn get_fps(&self) -> u16 {
         self.fps
     }
-    pub fn get_name(&self, binary: &[u8]) -> &str {
-        self.name.value(binary)
+    pub fn get_name<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.name.value(binary, "AnimEntry.name")
+    }
+    /// Get the user data offsets for each frame. Animations without user data have no table.
+    pub fn get_user_data<'a>(&self, binary: &'a [u8], num_frames: usize) -> Result<&'a [UserDataFrame], ParseError> {
+        match self.user_data.get_offset() {
+            0 => Ok(&[]),
+            _ => self.user_data.array(binary, num_frames)
+        }
+    }
+    pub fn get_num_labels(&self) -> u16 {
+        self.label_num
+    }
+    pub fn get_labels<'a>(&self, binary: &'a [u8]) -> Result<Vec<&'a LabelEntry>, ParseError> {
+        self.label_data.array(binary, self.label_num as usize)?
+            .iter().map(|p| p.value(binary)).collect()
     }
 
-    pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>, binary: &[u8],
-        parts: &[PartEntry], cells: &HashMap<usize, (u16, &str)>) -> std::io::Result<()> {
-        let anime_name = self.name.value(binary);
-        writer.create_element("name")
-            .write_text_content(BytesText::new(anime_name))?;
-        writer.create_element("overrideSettings")
-            .write_text_content(BytesText::new("1"))?;
-        writer.create_element("settings")
-            .write_inner_content(|writer| self.to_xml_settings(writer, binary))?;
-        writer.create_element("labels").write_empty()?;
-        writer.create_element("isSetup")
-            .write_text_content(BytesText::new(&format!("{}", (anime_name == "Setup") as u8)))?;
-        writer.create_element("partAnimes")
-            .write_inner_content(|writer| {
-                let mut attribute_writers: Vec<_> = (0..parts.len()).map(|i| {
-                    AttributeWriter::new(parts[i].get_name(binary))
-                }).collect();
-                let mut invisible_list: Vec<bool> = (0..parts.len()).map(|i| {
+    /// `ref_ranges` holds the (start, end) frames of the animation each instance part plays, from
+    /// [`Anime::get_ref_ranges`], and `texture_sizes` the size of each cell map's texture, from
+    /// [`ProjectHeader::get_texture_sizes`](crate::project::ProjectHeader::get_texture_sizes)
+    pub fn to_model(&self, file: &SsbpFile, parts: Table<PartEntry>, ref_ranges: &[Option<(u16, u16)>],
+        cells: &HashMap<usize, (u16, &str)>, texture_sizes: &HashMap<u16, Vec2>,
+        warnings: &mut Vec<model::Warning>) -> std::io::Result<model::Animation> {
+        let (binary, header, layout) = (file.get_binary(), file.get_header(), file.get_layout());
+        let anime_name = self.name.value(binary, "AnimEntry.name")?;
+        let is_setup = anime_name == "Setup";
+        let mut attribute_writers = Vec::with_capacity(parts.len());
+        for part in parts {
+            attribute_writers.push(AttributeWriter::new(part.get_name(binary)?));
+        }
+        let mut invisible_list: Vec<bool> = parts.iter()
+            .map(|p| matches!(p._type, PartType::instance | PartType::effect)).collect();
+        let mut last_effect_keyframe: HashMap<usize, EffectKeyframe> = HashMap::new();
+        let mut last_instance_keyframe: HashMap<usize, InstanceKeyframe> = HashMap::new();
+        let mut last_keyframe: Vec<HashMap<&'static str, (usize, AttributeKeyframe)>> = (0..parts.len()).map(|_| HashMap::new()).collect();
+        // mesh vertices relative to the part, used to turn the baked vertices into deform offsets
+        let mut mesh_rest_poses: Vec<Option<Vec<Vec2>>> = vec![None; parts.len()];
+        for (i, part) in parts.iter().enumerate() {
+            if part._type != PartType::mesh {
+                continue;
+            }
+            let cell_index = self.get_default_data(binary, parts.len(), &layout)?[i].cell_index;
+            if let Some(cell) = header.get_cells(binary)?.get(cell_index as usize) {
+                let rotated = texture_sizes.get(&cell.get_cell_map(binary)?.get_index())
+                    .is_some_and(|size| cell.is_rotated_in(*size));
+                mesh_rest_poses[i] = Some(self.get_mesh_uv(binary, parts.len())?[i].get_uvs(binary)?
+                    .iter().map(|uv| cell.point_to_local(cell.uv_to_point(*uv, rotated), rotated)).collect());
+            }
+        }
+        // (horizontal, vertical) flip state of each part, keyframed only when it changes
+        let mut flip_list: Vec<(bool, bool)> = vec![(false, false); parts.len()];
+        let user_data = self.get_user_data(binary, self.total_frames as usize)?;
+        let mut part_frames = self.get_part_frames(binary, parts.len(), &layout)?;
+        for f in 0..self.total_frames as usize {
+            if let Some(mut user) = user_data.get(f).map(|u| u.value(binary)).transpose()?.flatten() {
+                for _ in 0..user.read::<u16>(binary)? {
+                    let offset = user.get_offset();
+                    let start = user.read::<UserDataStart>(binary)?;
+                    let value = AttributeUserData::read(&mut user, binary, start.get_flags())?;
+                    attribute_writers.get_mut(start.get_part_index() as usize)
+                        .ok_or(ParseError::InvalidValue { name: "UserDataStart", offset, value: start.get_part_index() as usize })?
+                        .add_attribute(f, AttributeKeyframe::User(value));
+                }
+            }
+            // setup data is the same on every frame, so it's only keyframed once
+            if is_setup && f == 0 {
+                for (i, cell_setup) in self.get_default_data(binary, parts.len(), &layout)?.iter().enumerate() {
+                    let is_drawn = matches!(parts[i]._type, PartType::normal | PartType::mask | PartType::mesh);
+                    if is_drawn && !attribute_writers[i].has_attribute("CELL") {
+                        let index = cell_setup.cell_index;
+                        if let Some((map_id, cell_name)) = cells.get(&(index as usize)) {
+                            attribute_writers[i].add_attribute(f, AttributeKeyframe::Cell((*map_id, cell_name.to_string())));
+                        }
+                    }
+                    let hidden = cell_setup.lowflag.contains(LowFlag::PART_FLAG_INVISIBLE);
+                    if (is_drawn || hidden) && !attribute_writers[i].has_attribute("HIDE") {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::Hide(hidden as u16));
+                    }
+                    if cell_setup.lowflag.contains(LowFlag::PART_FLAG_FLIP_H) {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::FlipH(1));
+                    }
+                    if cell_setup.lowflag.contains(LowFlag::PART_FLAG_FLIP_V) {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::FlipV(1));
+                    }
+                    if cell_setup.position.x != 0. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::PositionX(cell_setup.position.x));
+                    }
+                    if cell_setup.position.y != 0. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::PositionY(cell_setup.position.y));
+                    }
+                    if cell_setup.position.z != 0. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::PositionZ(cell_setup.position.z));
+                    }
+                    if cell_setup.pivot.x != 0. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::PivotX(cell_setup.pivot.x));
+                    }
+                    if cell_setup.pivot.y != 0. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::PivotY(cell_setup.pivot.y));
+                    }
+                    if cell_setup.rotate.x != 0. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::RotationX(cell_setup.rotate.x));
+                    }
+                    if cell_setup.rotate.y != 0. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::RotationY(cell_setup.rotate.y));
+                    }
+                    if cell_setup.rotate.z != 0. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::RotationZ(cell_setup.rotate.z));
+                    }
+                    if cell_setup.scale.x != 1. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::ScaleX(cell_setup.scale.x));
+                    }
+                    if cell_setup.scale.y != 1. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::ScaleY(cell_setup.scale.y));
+                    }
+                    if cell_setup.local_scale.x != 1. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::LocalScaleX(cell_setup.local_scale.x));
+                    }
+                    if cell_setup.local_scale.y != 1. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::LocalScaleY(cell_setup.local_scale.y));
+                    }
+                    if cell_setup.opacity != 255 {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::Opacity(cell_setup.opacity as f32 / 255.));
+                    }
+                    if cell_setup.local_opacity != 255 {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::LocalOpacity(cell_setup.local_opacity as f32 / 255.));
+                    }
+                    if cell_setup.size.x != 0. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::SizeX(cell_setup.size.x));
+                    }
+                    if cell_setup.size.y != 0. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::SizeY(cell_setup.size.y));
+                    }
+                    if cell_setup.uv_move.x != 0. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::UVMoveU(cell_setup.uv_move.x));
+                    }
+                    if cell_setup.uv_move.y != 0. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::UVMoveV(cell_setup.uv_move.y));
+                    }
+                    if cell_setup.uv_rotate != 0. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::UVRotate(cell_setup.uv_rotate));
+                    }
+                    if cell_setup.uv_scale.x != 1. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::UVScaleU(cell_setup.uv_scale.x));
+                    }
+                    if cell_setup.uv_scale.y != 1. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::UVScaleV(cell_setup.uv_scale.y));
+                    }
+                    if cell_setup.bounding_radius != 0. {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::BoundingRadius(cell_setup.bounding_radius));
+                    }
+                    if cell_setup.masklimen != 0 {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::Mask(cell_setup.masklimen));
+                    }
+                    if cell_setup.priority != 0 {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::Prio(cell_setup.priority));
+                    }
+                    // the high flags only mark per-frame mesh vertices, which the setup data doesn't have
                     match parts[i]._type {
-                        PartType::instance | PartType::effect => true,
-                        _ => false
+                        PartType::instance => attribute_writers[i]
+                            .add_attribute(f, AttributeKeyframe::InstanceKeyframe(
+                                AttributeInstance::new(&cell_setup.get_instance_keyframe(), ref_ranges[i]))),
+                        PartType::effect => attribute_writers[i]
+                            .add_attribute(f, AttributeKeyframe::EffectKeyframe(cell_setup.get_effect_keyframe())),
+                        _ => ()
+                    };
+                }
+            } else if !is_setup {
+                // entries are stored in draw order, so `i` is the part each one names, not its
+                // position in the frame
+                for part_frame in part_frames.by_ref().take(parts.len()) {
+                    let PartFrame { part: i, state, .. } = part_frame?;
+                    let low_flag = state.low_flag;
+                    let cell_index = match low_flag.contains(LowFlag::PART_FLAG_CELL_INDEX) {
+                        true => Some(state.cell_index),
+                        false => match parts[i]._type {
+                            PartType::normal | PartType::mask | PartType::mesh => Some(state.cell_index)
+                                .filter(|c| cells.contains_key(&(*c as usize))),
+                            _ => None
+                        }
+                    };
+                    if let Some(cell_index) = cell_index {
+                        if let Some((map_id, cell_name)) = cells.get(&(cell_index as usize)) {
+                            let new = AttributeKeyframe::Cell((*map_id, cell_name.to_string()));
+                            attribute_writers[i].add_attribute_interp(&mut last_keyframe[i], f, new)?;
+                        } else {
+                            warnings.push(model::Warning::MissingCell { animation: anime_name.to_string(),
+                                part: attribute_writers[i].name.to_string(), frame: f, cell_index });
+                        }
                     }
-                }).collect();
-                let mut last_effect_keyframe: HashMap<usize, EffectKeyframe> = HashMap::new();
-                let mut last_keyframe: Vec<HashMap<LowFlag, (usize, AttributeKeyframe)>> = (0..parts.len()).map(|_| HashMap::new()).collect();
-                // let mut last_opacity: Vec<Option<(usize, f32)>> = vec![None; parts.len()];
-                // let mut invisible_list: Vec<bool> = vec![false; parts.len()];
-                for (f, frame) in self.get_frame_data(binary, self.total_frames as usize).iter().enumerate() {
-                    let mut data = frame.value(binary);
-                    if anime_name == "Setup" {
-                        for (i, cell_setup) in self.default_data.array(binary, parts.len()).iter().enumerate() {
-                            let check_cell = match parts[i]._type {
-                                PartType::normal | PartType::mask => true,
-                                _ => false
-                            };
-                            let check_hide = match parts[i]._type {
-                                PartType::normal | PartType::mask => true,
-                                _ => false
-                            };
-                            if check_cell && !attribute_writers[i].has_attribute("CELL"){
-                                let index = cell_setup.cell_index;
-                                if let Some((map_id, cell_name)) = cells.get(&(index as usize)) {
-                                    attribute_writers[i].add_attribute(f, AttributeKeyframe::Cell((*map_id, cell_name.to_string())));
-                                }
-                            }
-                            if check_hide && !attribute_writers[i].has_attribute("HIDE") {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::Hide(0));
-                            }
-                            if cell_setup.position.x != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::PositionX(cell_setup.position.x));
-                            }
-                            if cell_setup.position.y != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::PositionY(cell_setup.position.y));
-                            }
-                            if cell_setup.position.z != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::PositionZ(cell_setup.position.z));
-                            }
-                            if cell_setup.pivot.x != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::PivotX(cell_setup.pivot.x));
-                            }
-                            if cell_setup.pivot.y != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::PivotY(cell_setup.pivot.y));
-                            }
-                            if cell_setup.rotate.x != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::RotationX(cell_setup.rotate.x));
-                            }
-                            if cell_setup.rotate.y != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::RotationY(cell_setup.rotate.y));
-                            }
-                            if cell_setup.rotate.z != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::RotationZ(cell_setup.rotate.z));
-                            }
-                            if cell_setup.scale.x != 1. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::ScaleX(cell_setup.scale.x));
-                            }
-                            if cell_setup.scale.y != 1. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::ScaleY(cell_setup.scale.y));
-                            }
-                            if cell_setup.local_scale.x != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::LocalScaleX(cell_setup.local_scale.x));
-                            }
-                            if cell_setup.local_scale.y != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::LocalScaleY(cell_setup.local_scale.y));
-                            }
-                            if cell_setup.opacity != 255 {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::Opacity(cell_setup.opacity as f32 / 255.));
-                            }
-                            if cell_setup.local_opacity != 255 {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::Opacity(cell_setup.local_opacity as f32 / 255.));
-                            }
-                            if cell_setup.size.x != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::SizeX(cell_setup.size.x));
-                            }
-                            if cell_setup.size.y != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::SizeY(cell_setup.size.y));
-                            }
-                            if cell_setup.uv_move.x != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::UVMoveU(cell_setup.uv_move.x));
-                            }
-                            if cell_setup.uv_move.y != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::UVMoveV(cell_setup.uv_move.y));
-                            }
-                            if cell_setup.uv_rotate != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::UVRotate(cell_setup.uv_rotate));
-                            }
-                            if cell_setup.uv_scale.x != 1. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::UVScaleU(cell_setup.uv_scale.x));
-                            }
-                            if cell_setup.uv_scale.y != 1. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::UVScaleV(cell_setup.uv_scale.y));
-                            }
-                            if cell_setup.bounding_radius != 0. {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::BoundingRadius(cell_setup.bounding_radius));
-                            }
-                            if cell_setup.masklimen != 0 {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::Mask(cell_setup.masklimen));
-                            }
-                            if cell_setup.priority != 0 {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::Prio(cell_setup.priority));
-                            }
+                    if state.is_hidden() {
+                        if !invisible_list[i] {
+                            attribute_writers[i].add_attribute(f, AttributeKeyframe::Hide(1));
+                            invisible_list[i] = true;
                         }
-                    } else {
-                        for i in 0..parts.len() {
-                            let current = unsafe { data.read::<FrameStart>(binary) };
-                            let low_flag = current.get_low_flag(); // high flag is currently unused
-                            // if i != current.get_index() as usize {
-                            //     panic!("Index does not match! {} vs {}", i, current.get_index());
-                            // }
-                            // println!("{}, {:?}", current.get_index(),current.get_low_flag());
-                            if low_flag.contains(LowFlag::PART_FLAG_CELL_INDEX) {
-                                let cell_index = unsafe { data.read::<u16>(&binary) };
-                                if let Some((map_id, cell_name)) = cells.get(&(cell_index as usize)) {
-                                    attribute_writers[i].add_attribute(f, AttributeKeyframe::Cell((*map_id, cell_name.to_string())));
-                                } else {
-                                    println!("FAILED TO FIND frame {}, part {}: cell index: {}", f, i, cell_index);
-                                }
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_INVISIBLE) {
-                                // println!("frame {}, part {}: HIDE", f, i);
-                                if !invisible_list[i] {
-                                    attribute_writers[i].add_attribute(f, AttributeKeyframe::Hide(1));
-                                    invisible_list[i] = true;
-                                }
-                            } else if invisible_list[i] {
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::Hide(0));
-                            }
-                            /*
-                            if invisible_list[i] && !low_flag.contains(LowFlag::PART_FLAG_INVISIBLE) {
-                                invisible_list[i] = false;
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::Hide(0));
-                            }
-                            */
-                            if low_flag.contains(LowFlag::PART_FLAG_POSITION_X) {
-                                let new = AttributeKeyframe::PositionX(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_POSITION_X, new)?
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_POSITION_Y) {
-                                let new = AttributeKeyframe::PositionY(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_POSITION_Y, new)?
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_POSITION_Z) {
-                                let new = AttributeKeyframe::PositionZ(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_POSITION_Z, new)?
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_PIVOT_X) {
-                                let new = AttributeKeyframe::PivotX(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_PIVOT_X, new)?
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_PIVOT_Y) {
-                                let new = AttributeKeyframe::PivotY(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_PIVOT_Y, new)?
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_ROTATIONX) {
-                                let new = AttributeKeyframe::RotationX(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_ROTATIONX, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_ROTATIONY) {
-                                let new = AttributeKeyframe::RotationY(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_ROTATIONY, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_ROTATIONZ) {
-                                let new = AttributeKeyframe::RotationZ(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_ROTATIONZ, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_SCALE_X) {
-                                let new = AttributeKeyframe::ScaleX(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_SCALE_X, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_SCALE_Y) {
-                                let new = AttributeKeyframe::ScaleY(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_SCALE_Y, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_LOCALSCALE_X) {
-                                let new = AttributeKeyframe::LocalScaleX(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_LOCALSCALE_X, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_LOCALSCALE_Y) {
-                                let new = AttributeKeyframe::LocalScaleY(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_LOCALSCALE_Y, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_OPACITY) {
-                                let new = AttributeKeyframe::Opacity(unsafe { data.read::<u16>(&binary) } as f32 / 255.);
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_OPACITY, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_LOCALOPACITY) {
-                                let new = AttributeKeyframe::LocalOpacity(unsafe { data.read::<u16>(&binary) } as f32 / 255.);
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_LOCALOPACITY, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_SIZE_X) {
-                                let new = AttributeKeyframe::SizeX(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_SIZE_X, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_SIZE_Y) {
-                                let new = AttributeKeyframe::SizeY(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_SIZE_Y, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_U_MOVE) {
-                                let new = AttributeKeyframe::UVMoveU(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_U_MOVE, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_V_MOVE) {
-                                let new = AttributeKeyframe::UVMoveV(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_V_MOVE, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_UV_ROTATION) {
-                                let new = AttributeKeyframe::UVRotate(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_UV_ROTATION, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_U_SCALE) {
-                                let new = AttributeKeyframe::UVScaleU(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_U_SCALE, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_V_SCALE) {
-                                let new = AttributeKeyframe::UVScaleV(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_V_SCALE, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_BOUNDINGRADIUS) {
-                                let new = AttributeKeyframe::BoundingRadius(unsafe { data.read::<f32>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_BOUNDINGRADIUS, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_MASK) {
-                                let new = AttributeKeyframe::Mask(unsafe { data.read::<u16>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_MASK, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_PRIORITY) {
-                                let new = AttributeKeyframe::Prio(unsafe { data.read::<u16>(&binary) });
-                                attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_PRIORITY, new)?;
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_INSTANCE_KEYFRAME) {
-                                let new = unsafe { data.read::<InstanceKeyframe>(&binary) };
-                                attribute_writers[i].add_attribute(f, AttributeKeyframe::InstanceKeyframe(new));
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_EFFECT_KEYFRAME) {
-                                let new = unsafe { data.read::<EffectKeyframe>(&binary) };
-                                let allow_new = match last_effect_keyframe.get(&i) {
-                                    Some(k) => k != &new, None => true
-                                };
-                                if allow_new {
-                                    let  _ = last_effect_keyframe.insert(i, new.clone());
-                                    attribute_writers[i].add_attribute(f, AttributeKeyframe::EffectKeyframe(new));
-                                }
-                            }
-                            if low_flag.contains(LowFlag::PART_FLAG_PARTS_COLOR) {
-                                let type_and_flags = unsafe { data.read::<u16>(&binary) };
-                                let flag = ColorAttributeFlags::from_bits_truncate(type_and_flags >> 8);
-                                let blend: BlendType = (type_and_flags & 0xff).try_into().map_err(|e| std::io::Error::other(e))?;
-                                if flag.contains(ColorAttributeFlags::VERTEX_FLAG_ONE) {
-                                    let color = unsafe { data.read::<ColorAttribute>(&binary) };
-                                    let new = AttributeKeyframe::PartsColor(AttributePartsColor::new_one(&color, blend));
-                                    attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_PARTS_COLOR, new)?;
-                                } else {
-                                    if flag != ColorAttributeFlags::from_bits_truncate(0xf) {
-                                        return Err(std::io::Error::other(AttributeError::PartsColorMisingVertices));
-                                    }
-                                    let colors = [
-                                        unsafe { data.read::<ColorAttribute>(&binary) },
-                                        unsafe { data.read::<ColorAttribute>(&binary) },
-                                        unsafe { data.read::<ColorAttribute>(&binary) },
-                                        unsafe { data.read::<ColorAttribute>(&binary) },
-                                    ];
-                                    let new = AttributeKeyframe::PartsColor(AttributePartsColor::new_vertex(&colors, blend));
-                                    attribute_writers[i].attribute_to_xml_interp(&mut last_keyframe[i], f, LowFlag::PART_FLAG_PARTS_COLOR, new)?;
-                                }
-                            }
+                    } else if invisible_list[i] {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::Hide(0));
+                        invisible_list[i] = false;
+                    }
+                    let flip_h = state.is_flip_h();
+                    if flip_h != flip_list[i].0 {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::FlipH(flip_h as u16));
+                        flip_list[i].0 = flip_h;
+                    }
+                    let flip_v = state.is_flip_v();
+                    if flip_v != flip_list[i].1 {
+                        attribute_writers[i].add_attribute(f, AttributeKeyframe::FlipV(flip_v as u16));
+                        flip_list[i].1 = flip_v;
+                    }
+                    for new in [
+                        AttributeKeyframe::PositionX(state.position.x),
+                        AttributeKeyframe::PositionY(state.position.y),
+                        AttributeKeyframe::PositionZ(state.position.z),
+                        AttributeKeyframe::PivotX(state.pivot.x),
+                        AttributeKeyframe::PivotY(state.pivot.y),
+                        AttributeKeyframe::RotationX(state.rotation.x),
+                        AttributeKeyframe::RotationY(state.rotation.y),
+                        AttributeKeyframe::RotationZ(state.rotation.z),
+                        AttributeKeyframe::ScaleX(state.scale.x),
+                        AttributeKeyframe::ScaleY(state.scale.y),
+                        AttributeKeyframe::LocalScaleX(state.local_scale.x),
+                        AttributeKeyframe::LocalScaleY(state.local_scale.y),
+                        AttributeKeyframe::Opacity(state.opacity as f32 / 255.),
+                        AttributeKeyframe::LocalOpacity(state.local_opacity as f32 / 255.),
+                        AttributeKeyframe::SizeX(state.size.x),
+                        AttributeKeyframe::SizeY(state.size.y),
+                        AttributeKeyframe::UVMoveU(state.uv_move.x),
+                        AttributeKeyframe::UVMoveV(state.uv_move.y),
+                        AttributeKeyframe::UVRotate(state.uv_rotate),
+                        AttributeKeyframe::UVScaleU(state.uv_scale.x),
+                        AttributeKeyframe::UVScaleV(state.uv_scale.y),
+                        AttributeKeyframe::BoundingRadius(state.bounding_radius),
+                        AttributeKeyframe::Mask(state.mask),
+                        AttributeKeyframe::Prio(state.priority),
+                    ] {
+                        attribute_writers[i].add_attribute_interp(&mut last_keyframe[i], f, new)?;
+                    }
+                    // parts without a keyframe on the first frame start from their initial value
+                    let instance = Some(&state.instance).filter(|_| low_flag.contains(LowFlag::PART_FLAG_INSTANCE_KEYFRAME)
+                        || (f == 0 && parts[i]._type == PartType::instance));
+                    // every frame repeats the last instance keyframe, which knows when it was placed
+                    if let Some(new) = instance.filter(|k| last_instance_keyframe.get(&i) != Some(k)) {
+                        let time = new.get_current_frame() as usize;
+                        let value = AttributeInstance::new(new, ref_ranges[i]);
+                        last_instance_keyframe.insert(i, new.clone());
+                        attribute_writers[i].add_attribute(time, AttributeKeyframe::InstanceKeyframe(value));
+                    }
+                    let effect = Some(&state.effect).filter(|_| low_flag.contains(LowFlag::PART_FLAG_EFFECT_KEYFRAME)
+                        || (f == 0 && parts[i]._type == PartType::effect));
+                    // like instances, every frame repeats the effect keyframe placed before it
+                    if let Some(new) = effect.filter(|k| last_effect_keyframe.get(&i) != Some(k)) {
+                        let time = new.get_current_frame() as usize;
+                        last_effect_keyframe.insert(i, new.clone());
+                        attribute_writers[i].add_attribute(time, AttributeKeyframe::EffectKeyframe(new.clone()));
+                    }
+                    if let Some(transform) = state.vertex_transform {
+                        let new = AttributeKeyframe::VertexTransform(transform);
+                        attribute_writers[i].add_attribute_interp(&mut last_keyframe[i], f, new)?;
+                    }
+                    if let Some(color) = state.parts_color {
+                        let new = AttributeKeyframe::PartsColor(color);
+                        attribute_writers[i].add_attribute_interp(&mut last_keyframe[i], f, new)?;
+                    }
+                    if let Some(vertices) = state.mesh_vertices {
+                        match &mesh_rest_poses[i] {
+                            Some(rest) if rest.len() == vertices.len() => {
+                                let new = AttributeKeyframe::Deform(AttributeDeform::new(rest, &vertices));
+                                attribute_writers[i].add_attribute_interp(&mut last_keyframe[i], f, new)?;
+                            },
+                            _ => warnings.push(model::Warning::MismatchedMesh { animation: anime_name.to_string(),
+                                part: attribute_writers[i].name.to_string(), frame: f, vertices: vertices.len() })
                         }
                     }
                 }
-                for attrib_writer in attribute_writers {
-                    if !attrib_writer.has_attributes() {
-                        continue;
-                    }
-                    let name = attrib_writer.name;
+            }
+        }
+        // parts are drawn at their cell's size unless the size is keyframed
+        let cell_entries = header.get_cells(binary)?;
+        for (i, initial) in self.get_default_data(binary, parts.len(), &layout)?.iter().enumerate()
+            .filter(|_| !is_setup) {
+            let cell_size = match parts[i]._type {
+                PartType::normal | PartType::mask | PartType::mesh => cell_entries
+                    .get(initial.cell_index as usize).map(|c| c.get_size().as_vec2()),
+                _ => None
+            };
+            attribute_writers[i].remove_implicit_defaults(cell_size);
+        }
+        let part_animes = attribute_writers.into_iter()
+            .filter(|w| w.has_attributes())
+            .map(|w| w.into_part_anime())
+            .collect();
+        let mut labels = Vec::with_capacity(self.label_num as usize);
+        for label in self.get_labels(binary)? {
+            labels.push(model::Label { name: label.get_name(binary)?.to_string(), time: label.get_time() });
+        }
+        Ok(model::Animation {
+            name: anime_name.to_string(),
+            start_frame: self.start_frames,
+            end_frame: self.end_frames,
+            total_frames: self.total_frames,
+            fps: self.fps,
+            canvas_size: UVec2::new(self.canvas_size_w as u32, self.canvas_size_h as u32),
+            canvas_pivot: Vec2::new(self.canvas_pivot_x, self.canvas_pivot_y),
+            labels,
+            part_animes
+        })
+    }
+}
+
+impl model::Animation {
+    /// Animations whose settings differ from the anime pack's override them
+    pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>, pack_settings: &model::AnimeSettings)
+        -> std::io::Result<()> {
+        writer.create_element("name")
+            .write_text_content(BytesText::new(&self.name))?;
+        writer.create_element("overrideSettings")
+            .write_text_content(BytesText::new(&format!("{}", (self.get_settings() != *pack_settings) as u8)))?;
+        writer.create_element("settings")
+            .write_inner_content(|writer| self.to_xml_settings(writer))?;
+        let labels = writer.create_element("labels");
+        match self.labels.len() {
+            0 => labels.write_empty(),
+            _ => labels.write_inner_content(|writer| {
+                for label in &self.labels {
+                    writer.create_element("value")
+                        .write_inner_content(|writer| {
+                            writer.create_element("name")
+                                .write_text_content(BytesText::new(&label.name))?;
+                            writer.create_element("time")
+                                .write_text_content(BytesText::new(&format!("{}", label.time)))?;
+                            Ok(())
+                        })?;
+                }
+                Ok(())
+            })
+        }?;
+        writer.create_element("isSetup")
+            .write_text_content(BytesText::new(&format!("{}", self.is_setup() as u8)))?;
+        writer.create_element("partAnimes")
+            .write_inner_content(|writer| {
+                for part_anime in &self.part_animes {
                     writer.create_element("partAnime")
                         .write_inner_content(|writer| {
                             writer.create_element("partName")
-                                .write_text_content(BytesText::new(name))?;
+                                .write_text_content(BytesText::new(&part_anime.part_name))?;
                             writer.create_element("attributes")
-                                .write_inner_content(|writer| attrib_writer.write_attributes(writer, anime_name == "Setup"))?;
+                                .write_inner_content(|writer| part_anime.write_attributes(writer, self.is_setup()))?;
                             Ok(())
                         })?;
                 }
@@ -563,7 +670,7 @@ impl AnimEntry {
         Ok(())
     }
 
-    pub(crate) fn to_xml_settings<W: Write + Seek>(&self, writer: &mut Writer<W>, binary: &[u8])
+    pub(crate) fn to_xml_settings<W: Write + Seek>(&self, writer: &mut Writer<W>)
                                                    -> std::io::Result<()> {
         writer.create_element("fps")
             .write_text_content(BytesText::new(&format!("{}", self.fps)))?;
@@ -572,9 +679,9 @@ impl AnimEntry {
         writer.create_element("sortMode")
             .write_text_content(BytesText::new("prio"))?;
         writer.create_element("canvasSize")
-            .write_text_content(BytesText::new(&format!("{} {}", self.canvas_size_w, self.canvas_size_h)))?;
+            .write_text_content(BytesText::new(&format!("{} {}", self.canvas_size.x, self.canvas_size.y)))?;
         writer.create_element("pivot")
-            .write_text_content(BytesText::new(&format!("{} {}", self.canvas_pivot_x, self.canvas_pivot_y)))?;
+            .write_text_content(BytesText::new(&format!("{} {}", self.canvas_pivot.x, self.canvas_pivot.y)))?;
         writer.create_element("gridSize")
             .write_text_content(BytesText::new("32"))?;
         writer.create_element("gridColor")
@@ -582,9 +689,9 @@ impl AnimEntry {
         writer.create_element("ik_depth")
             .write_text_content(BytesText::new("3"))?;
         writer.create_element("startFrame")
-            .write_text_content(BytesText::new(&format!("{}", self.start_frames)))?;
+            .write_text_content(BytesText::new(&format!("{}", self.start_frame)))?;
         writer.create_element("endFrame")
-            .write_text_content(BytesText::new(&format!("{}", self.end_frames)))?;
+            .write_text_content(BytesText::new(&format!("{}", self.end_frame)))?;
         writer.create_element("bgSettings")
             .write_inner_content(|writer| to_xml_anime_bg_settings(writer))?;
         writer.create_element("outStartNum")
@@ -679,6 +786,48 @@ pub struct AnimInitial {
     effect_loop_flag: u32
 }
 
+const _: () = {
+    ["Size of AnimInitial"][size_of::<AnimInitial>() - 0x90];
+};
+
+unsafe impl BinaryStruct for AnimInitial {
+    const NAME: &'static str = "AnimInitial";
+}
+
+impl AnimInitial {
+    pub fn get_cell_index(&self) -> u16 {
+        self.cell_index
+    }
+    pub fn get_low_flag(&self) -> LowFlag {
+        self.lowflag
+    }
+    pub fn get_high_flag(&self) -> HighFlag {
+        self.highflag
+    }
+
+    /// Instance settings in the same layout as an IPRM frame
+    pub fn get_instance_keyframe(&self) -> InstanceKeyframe {
+        InstanceKeyframe {
+            current_frame: self.instance_current_frame,
+            start_frame: self.instance_start_frame,
+            end_frame: self.instance_end_frame,
+            loop_num: self.instance_loop_num,
+            speed: self.instance_speed,
+            loop_flag: InstanceLoopFlags::from_bits_retain(self.instance_loop_flag),
+        }
+    }
+
+    /// Effect settings in the same layout as an EFCT frame
+    pub fn get_effect_keyframe(&self) -> EffectKeyframe {
+        EffectKeyframe {
+            current_frame: self.effect_current_frame,
+            start_time: self.effect_start_time,
+            speed: self.effect_speed,
+            loop_flag: EffectLoopFlags::from_bits_retain(self.effect_loop_flag),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct FramePart(usize);
@@ -691,6 +840,10 @@ pub struct FrameStart {
     high_flag: HighFlag
 }
 
+unsafe impl BinaryStruct for FrameStart {
+    const NAME: &'static str = "FrameStart";
+}
+
 impl FrameStart {
     pub fn get_index(&self) -> u16 {
         self.index
@@ -704,28 +857,237 @@ impl FrameStart {
 }
 
 impl FramePart {
-    pub unsafe fn read<T>(&mut self, binary: &[u8]) -> T {
-        let value = unsafe { std::ptr::read(binary.as_ptr().add(self.0) as *const T) };
-        // let value = unsafe { &*(binary.as_ptr().add(self.0) as *const T) };
+    pub fn read<T: BinaryStruct>(&mut self, binary: &[u8]) -> Result<T, ParseError> {
+        check_range::<T>(binary, self.0, 1)?;
+        T::validate(binary, self.0)?;
+        let value = unsafe { std::ptr::read_unaligned(binary.as_ptr().add(self.0) as *const T) };
         self.0 += size_of::<T>();
-        value
+        Ok(value)
     }
-    pub unsafe fn advance(&mut self, bytes: usize) {
+    pub fn advance(&mut self, bytes: usize) {
         self.0 += bytes;
     }
+    pub fn get_offset(&self) -> usize {
+        self.0
+    }
 }
 
 #[repr(C)]
 #[derive(Debug)]
 pub struct FrameData(Ptr<()>);
 
+unsafe impl BinaryStruct for FrameData {
+    const NAME: &'static str = "FrameData";
+}
+
 impl FrameData {
-   pub fn value(&self, binary: &[u8]) -> FramePart {
-       let offset = ((&raw const *self.0.value(binary)) as usize) - (binary.as_ptr() as usize);
-       FramePart(offset)
+   pub fn value(&self, binary: &[u8]) -> Result<FramePart, ParseError> {
+       let offset = self.0.get_offset();
+       match offset < binary.len() {
+           true => Ok(FramePart(offset)),
+           false => Err(ParseError::OutOfBounds { name: "FramePart", offset, size: 1 })
+       }
    }
 }
 
+/// Read a value from the frame if its flag is set, otherwise use the part's initial value
+fn read_or<T: BinaryStruct>(data: &mut FramePart, binary: &[u8], stored: bool, initial: T) -> Result<T, ParseError> {
+    match stored {
+        true => data.read::<T>(binary),
+        false => Ok(initial)
+    }
+}
+
+/// Full set of attributes for a part on one frame. Attributes that aren't stored in the frame
+/// hold the part's initial value, and the flags show which ones were stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartState {
+    pub low_flag: LowFlag,
+    pub high_flag: HighFlag,
+    pub cell_index: u16,
+    pub position: Vec3,
+    pub pivot: Vec2,
+    pub rotation: Vec3,
+    pub scale: Vec2,
+    pub local_scale: Vec2,
+    /// From 0 to 255
+    pub opacity: u16,
+    /// From 0 to 255
+    pub local_opacity: u16,
+    pub size: Vec2,
+    pub uv_move: Vec2,
+    pub uv_rotate: f32,
+    pub uv_scale: Vec2,
+    pub bounding_radius: f32,
+    pub mask: u16,
+    pub priority: u16,
+    pub instance: InstanceKeyframe,
+    pub effect: EffectKeyframe,
+    pub vertex_transform: Option<AttributeVertexTransform>,
+    pub parts_color: Option<AttributePartsColor>,
+    /// Mesh vertices after bone deformation, relative to the part
+    pub mesh_vertices: Option<Vec<Vec3>>,
+}
+
+impl PartState {
+    /// Read the rest of one part's entry from a frame after its [`FrameStart`], in the order the
+    /// attributes are stored
+    pub fn read(data: &mut FramePart, binary: &[u8], start: &FrameStart, initial: &AnimInitial) -> std::io::Result<Self> {
+        let (low_flag, high_flag) = (start.get_low_flag(), start.get_high_flag());
+        // payloads for unknown high flags can't be skipped, so continuing would read the rest of
+        // the frame from the wrong offset
+        if HighFlag::from_bits(high_flag.bits()).is_none() {
+            return Err(std::io::Error::other(AttributeError::UnhandledHighFlag(high_flag.bits())));
+        }
+        let read_f32 = |data: &mut FramePart, flag, initial| read_or(data, binary, low_flag.contains(flag), initial);
+        let cell_index = read_or(data, binary, low_flag.contains(LowFlag::PART_FLAG_CELL_INDEX), initial.cell_index)?;
+        let position = Vec3::new(
+            read_f32(data, LowFlag::PART_FLAG_POSITION_X, initial.position.x)?,
+            read_f32(data, LowFlag::PART_FLAG_POSITION_Y, initial.position.y)?,
+            read_f32(data, LowFlag::PART_FLAG_POSITION_Z, initial.position.z)?);
+        let pivot = Vec2::new(
+            read_f32(data, LowFlag::PART_FLAG_PIVOT_X, initial.pivot.x)?,
+            read_f32(data, LowFlag::PART_FLAG_PIVOT_Y, initial.pivot.y)?);
+        let rotation = Vec3::new(
+            read_f32(data, LowFlag::PART_FLAG_ROTATIONX, initial.rotate.x)?,
+            read_f32(data, LowFlag::PART_FLAG_ROTATIONY, initial.rotate.y)?,
+            read_f32(data, LowFlag::PART_FLAG_ROTATIONZ, initial.rotate.z)?);
+        let scale = Vec2::new(
+            read_f32(data, LowFlag::PART_FLAG_SCALE_X, initial.scale.x)?,
+            read_f32(data, LowFlag::PART_FLAG_SCALE_Y, initial.scale.y)?);
+        let local_scale = Vec2::new(
+            read_f32(data, LowFlag::PART_FLAG_LOCALSCALE_X, initial.local_scale.x)?,
+            read_f32(data, LowFlag::PART_FLAG_LOCALSCALE_Y, initial.local_scale.y)?);
+        let opacity = read_or(data, binary, low_flag.contains(LowFlag::PART_FLAG_OPACITY), initial.opacity)?;
+        let local_opacity = read_or(data, binary, low_flag.contains(LowFlag::PART_FLAG_LOCALOPACITY), initial.local_opacity)?;
+        let size = Vec2::new(
+            read_f32(data, LowFlag::PART_FLAG_SIZE_X, initial.size.x)?,
+            read_f32(data, LowFlag::PART_FLAG_SIZE_Y, initial.size.y)?);
+        let uv_move = Vec2::new(
+            read_f32(data, LowFlag::PART_FLAG_U_MOVE, initial.uv_move.x)?,
+            read_f32(data, LowFlag::PART_FLAG_V_MOVE, initial.uv_move.y)?);
+        let uv_rotate = read_f32(data, LowFlag::PART_FLAG_UV_ROTATION, initial.uv_rotate)?;
+        let uv_scale = Vec2::new(
+            read_f32(data, LowFlag::PART_FLAG_U_SCALE, initial.uv_scale.x)?,
+            read_f32(data, LowFlag::PART_FLAG_V_SCALE, initial.uv_scale.y)?);
+        let bounding_radius = read_f32(data, LowFlag::PART_FLAG_BOUNDINGRADIUS, initial.bounding_radius)?;
+        let mask = read_or(data, binary, low_flag.contains(LowFlag::PART_FLAG_MASK), initial.masklimen)?;
+        let priority = read_or(data, binary, low_flag.contains(LowFlag::PART_FLAG_PRIORITY), initial.priority)?;
+        let instance = read_or(data, binary, low_flag.contains(LowFlag::PART_FLAG_INSTANCE_KEYFRAME),
+            initial.get_instance_keyframe())?;
+        let effect = read_or(data, binary, low_flag.contains(LowFlag::PART_FLAG_EFFECT_KEYFRAME),
+            initial.get_effect_keyframe())?;
+        let vertex_transform = match low_flag.contains(LowFlag::PART_FLAG_VERTEX_TRANSFORM) {
+            true => {
+                let raw_flags = data.read::<u16>(binary)?;
+                let flag = ColorAttributeFlags::from_bits(raw_flags)
+                    .filter(|f| !f.contains(ColorAttributeFlags::VERTEX_FLAG_ONE))
+                    .ok_or(std::io::Error::other(AttributeError::UnknownVertexFlags(raw_flags)))?;
+                Some(AttributeVertexTransform::read(data, binary, flag)?)
+            },
+            false => None
+        };
+        let parts_color = match low_flag.contains(LowFlag::PART_FLAG_PARTS_COLOR) {
+            true => {
+                let type_and_flags = data.read::<u16>(binary)?;
+                let flag = ColorAttributeFlags::from_bits_truncate(type_and_flags >> 8);
+                let blend: BlendType = (type_and_flags & 0xff).try_into().map_err(std::io::Error::other)?;
+                if flag.contains(ColorAttributeFlags::VERTEX_FLAG_ONE) {
+                    Some(AttributePartsColor::new_one(&data.read::<ColorAttribute>(binary)?, blend))
+                } else {
+                    if flag != ColorAttributeFlags::from_bits_truncate(0xf) {
+                        return Err(std::io::Error::other(AttributeError::PartsColorMisingVertices));
+                    }
+                    let colors = [
+                        data.read::<ColorAttribute>(binary)?,
+                        data.read::<ColorAttribute>(binary)?,
+                        data.read::<ColorAttribute>(binary)?,
+                        data.read::<ColorAttribute>(binary)?,
+                    ];
+                    Some(AttributePartsColor::new_vertex(&colors, blend))
+                }
+            },
+            false => None
+        };
+        let mesh_vertices = match high_flag.contains(HighFlag::PART_FLAG_MESHDATA) {
+            true => Some((0..data.read::<u32>(binary)?)
+                .map(|_| data.read::<Vec3>(binary))
+                .collect::<Result<Vec<_>, _>>()?),
+            false => None
+        };
+        Ok(Self {
+            low_flag, high_flag, cell_index, position, pivot, rotation, scale, local_scale,
+            opacity, local_opacity, size, uv_move, uv_rotate, uv_scale, bounding_radius, mask, priority,
+            instance, effect, vertex_transform, parts_color, mesh_vertices
+        })
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.low_flag.contains(LowFlag::PART_FLAG_INVISIBLE)
+    }
+    pub fn is_flip_h(&self) -> bool {
+        self.low_flag.contains(LowFlag::PART_FLAG_FLIP_H)
+    }
+    pub fn is_flip_v(&self) -> bool {
+        self.low_flag.contains(LowFlag::PART_FLAG_FLIP_V)
+    }
+}
+
+/// State of one part on one frame, where `part` is the part's position in the anime pack. Parts
+/// are stored in the order they're drawn, so they don't come in this order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartFrame {
+    pub frame: usize,
+    pub part: usize,
+    pub state: PartState,
+}
+
+/// Decodes an animation's frame stream, yielding every part of a frame (in the order they're
+/// stored) before moving on to the next frame. Stops after the first error, since the rest of the
+/// stream can't be located.
+pub struct FrameIterator<'a> {
+    binary: &'a [u8],
+    frames: &'a [FrameData],
+    initial: Table<'a, AnimInitial>,
+    frame: usize,
+    /// Number of part entries already read from the current frame
+    entry: usize,
+    data: Option<FramePart>,
+}
+
+impl<'a> FrameIterator<'a> {
+    fn read_next(&mut self) -> std::io::Result<PartFrame> {
+        let data = match &mut self.data {
+            Some(data) => data,
+            None => self.data.insert(self.frames[self.frame].value(self.binary)?)
+        };
+        let offset = data.get_offset();
+        let start = data.read::<FrameStart>(self.binary)?;
+        let part = start.get_index() as usize;
+        let initial = self.initial.get(part)
+            .ok_or(ParseError::InvalidValue { name: FrameStart::NAME, offset, value: part })?;
+        let state = PartState::read(data, self.binary, &start, initial)?;
+        Ok(PartFrame { frame: self.frame, part, state })
+    }
+}
+
+impl<'a> Iterator for FrameIterator<'a> {
+    type Item = std::io::Result<PartFrame>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.frame >= self.frames.len() || self.initial.is_empty() {
+            return None;
+        }
+        let result = self.read_next();
+        self.entry += 1;
+        if self.entry == self.initial.len() || result.is_err() {
+            self.entry = 0;
+            self.frame = match result.is_err() { true => self.frames.len(), false => self.frame + 1 };
+            self.data = None;
+        }
+        Some(result)
+    }
+}
+
 bitflags! {
     #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
     pub struct ColorAttributeFlags : u16 {
@@ -744,9 +1106,15 @@ pub struct ColorAttribute {
     rgba: u32
 }
 
+unsafe impl BinaryStruct for ColorAttribute {
+    const NAME: &'static str = "ColorAttribute";
+}
+
 #[derive(Debug)]
 pub enum AttributeError {
-    PartsColorMisingVertices
+    PartsColorMisingVertices,
+    UnknownVertexFlags(u16),
+    UnhandledHighFlag(u32),
 }
 
 impl Error for AttributeError {}
@@ -758,6 +1126,7 @@ impl Display for AttributeError {
 
 bitflags! {
     #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct InstanceLoopFlags : u32 {
 	    const INFINITY		= 1 << 0;
 	    const REVERSE		= 1 << 1;
@@ -768,6 +1137,7 @@ bitflags! {
 
 #[repr(C, packed(2))]
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InstanceKeyframe {
     current_frame: u32,
     start_frame: u32,
@@ -777,28 +1147,313 @@ pub struct InstanceKeyframe {
     loop_flag: InstanceLoopFlags,
 }
 
+unsafe impl BinaryStruct for InstanceKeyframe {
+    const NAME: &'static str = "InstanceKeyframe";
+}
+
+impl InstanceKeyframe {
+    pub fn get_current_frame(&self) -> u32 {
+        self.current_frame
+    }
+    pub fn get_start_frame(&self) -> u32 {
+        self.start_frame
+    }
+    pub fn get_end_frame(&self) -> u32 {
+        self.end_frame
+    }
+    pub fn get_loop_num(&self) -> u32 {
+        self.loop_num
+    }
+    pub fn get_speed(&self) -> f32 {
+        self.speed
+    }
+    pub fn get_loop_flag(&self) -> InstanceLoopFlags {
+        self.loop_flag
+    }
+}
+
+/// Instance settings for an IPRM key. The binary stores the frames the referenced animation plays
+/// between, which SpriteStudio expresses as offsets from its _start and _end labels.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AttributeInstance {
+    loop_num: u32,
+    start_label: String,
+    start_offset: i32,
+    end_label: String,
+    end_offset: i32,
+    speed: f32,
+    loop_flag: InstanceLoopFlags
+}
+
+impl AttributeInstance {
+    /// `ref_range` is the start and end frame of the referenced animation. Without it, both
+    /// offsets are measured from the first frame.
+    pub fn new(key: &InstanceKeyframe, ref_range: Option<(u16, u16)>) -> Self {
+        let (start_frame, end_frame) = (key.get_start_frame() as i32, key.get_end_frame() as i32);
+        let (start_label, start_offset, end_label, end_offset) = match ref_range {
+            Some((start, end)) => ("_start", start_frame - start as i32, "_end", end_frame - end as i32),
+            None => ("_start", start_frame, "_start", end_frame)
+        };
+        Self {
+            loop_num: key.get_loop_num(),
+            start_label: start_label.to_string(),
+            start_offset,
+            end_label: end_label.to_string(),
+            end_offset,
+            speed: key.get_speed(),
+            loop_flag: key.get_loop_flag()
+        }
+    }
+
+    fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
+        for (name, flag) in [("infinity", InstanceLoopFlags::INFINITY), ("reverse", InstanceLoopFlags::REVERSE),
+            ("pingpong", InstanceLoopFlags::PINGPONG), ("independent", InstanceLoopFlags::INDEPENDENT)] {
+            writer.create_element(name)
+                .write_text_content(BytesText::new(&format!("{}", self.loop_flag.contains(flag) as u8)))?;
+        }
+        writer.create_element("loopNum")
+            .write_text_content(BytesText::new(&format!("{}", self.loop_num)))?;
+        writer.create_element("startLabel")
+            .write_text_content(BytesText::new(&self.start_label))?;
+        writer.create_element("startOffset")
+            .write_text_content(BytesText::new(&format!("{}", self.start_offset)))?;
+        writer.create_element("endLabel")
+            .write_text_content(BytesText::new(&self.end_label))?;
+        writer.create_element("endOffset")
+            .write_text_content(BytesText::new(&format!("{}", self.end_offset)))?;
+        writer.create_element("speed")
+            .write_text_content(BytesText::new(&format!("{}", self.speed)))?;
+        Ok(())
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct EffectLoopFlags : u32 {
+        /// SS_EFFECT_LOOP_FLAG_INFINITY, the only loop flag. The converter sets it from the ssae
+        /// `independent` key: independent effects keep looping on their own timeline instead of
+        /// following the part's keyframes
+        const INFINITY = 1 << 0;
+    }
+}
+
 #[repr(C, packed(2))]
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EffectKeyframe {
     current_frame: u32,
     start_time: u32,
     speed: f32,
-    loop_flag: u32
+    loop_flag: EffectLoopFlags
+}
+
+unsafe impl BinaryStruct for EffectKeyframe {
+    const NAME: &'static str = "EffectKeyframe";
+}
+
+impl EffectKeyframe {
+    pub fn get_current_frame(&self) -> u32 {
+        self.current_frame
+    }
+    pub fn get_start_time(&self) -> u32 {
+        self.start_time
+    }
+    pub fn get_speed(&self) -> f32 {
+        self.speed
+    }
+    pub fn get_loop_flag(&self) -> EffectLoopFlags {
+        self.loop_flag
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct UserDataFrame(Ptr<()>);
+
+unsafe impl BinaryStruct for UserDataFrame {
+    const NAME: &'static str = "UserDataFrame";
+}
+
+impl UserDataFrame {
+    /// Frames without user data have an offset of 0
+    pub fn value(&self, binary: &[u8]) -> Result<Option<FramePart>, ParseError> {
+        let offset = self.0.get_offset();
+        match offset {
+            0 => Ok(None),
+            o if o < binary.len() => Ok(Some(FramePart(offset))),
+            _ => Err(ParseError::OutOfBounds { name: "UserDataFrame", offset, size: 1 })
+        }
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+    pub struct UserDataFlags : u16 {
+        const INTEGER = 1 << 0;
+        const RECT = 1 << 1;
+        const POINT = 1 << 2;
+        const STRING = 1 << 3;
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct UserDataStart {
+    flags: UserDataFlags,
+    part_index: u16
+}
+
+unsafe impl BinaryStruct for UserDataStart {
+    const NAME: &'static str = "UserDataStart";
+}
+
+impl UserDataStart {
+    pub fn get_flags(&self) -> UserDataFlags {
+        self.flags
+    }
+    pub fn get_part_index(&self) -> u16 {
+        self.part_index
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UserDataInteger {
+    integer: i32
+}
+
+unsafe impl BinaryStruct for UserDataInteger {
+    const NAME: &'static str = "UserDataInteger";
+}
+
+impl UserDataInteger {
+    pub fn get_integer(&self) -> i32 {
+        self.integer
+    }
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UserDataRect {
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32
+}
+
+unsafe impl BinaryStruct for UserDataRect {
+    const NAME: &'static str = "UserDataRect";
+}
+
+impl UserDataRect {
+    pub fn get_left(&self) -> i32 {
+        self.left
+    }
+    pub fn get_top(&self) -> i32 {
+        self.top
+    }
+    pub fn get_right(&self) -> i32 {
+        self.right
+    }
+    pub fn get_bottom(&self) -> i32 {
+        self.bottom
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UserDataPoint {
+    x: i32,
+    y: i32
+}
+
+unsafe impl BinaryStruct for UserDataPoint {
+    const NAME: &'static str = "UserDataPoint";
+}
+
+impl UserDataPoint {
+    pub fn get_x(&self) -> i32 {
+        self.x
+    }
+    pub fn get_y(&self) -> i32 {
+        self.y
+    }
+}
+
+#[repr(C, packed(2))]
+#[derive(Debug)]
+pub struct UserDataString {
+    length: u16,
+    string: StringPtr
+}
+
+unsafe impl BinaryStruct for UserDataString {
+    const NAME: &'static str = "UserDataString";
+}
+
+impl UserDataString {
+    pub fn get_length(&self) -> u16 {
+        self.length
+    }
+    pub fn get_string<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        let string = self.string;
+        string.value(binary, "UserDataString.string")
+    }
+}
+
+/// Offset of a part's mesh UVs. Parts that aren't meshes only have a zeroed header type.
 #[repr(C)]
 #[derive(Debug)]
 pub struct MeshUV(Ptr<MeshUVData>);
 
+unsafe impl BinaryStruct for MeshUV {
+    const NAME: &'static str = "MeshUV";
+}
+
+impl MeshUV {
+    /// Get the texture coordinates of each vertex in the mesh
+    pub fn get_uvs<'a>(&self, binary: &'a [u8]) -> Result<&'a [Vec2], ParseError> {
+        let offset = self.0.get_offset();
+        if *Ptr::<u32>::new(offset as u32).value(binary)? == 0 {
+            return Ok(&[]);
+        }
+        let data = self.0.value(binary)?;
+        Ptr::<Vec2>::new((offset + size_of::<MeshUVData>()) as u32).array(binary, data.num_uv as usize)
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct MeshUVData {
+    mesh_type: u32,
+    num_uv: u32
+}
 
+unsafe impl BinaryStruct for MeshUVData {
+    const NAME: &'static str = "MeshUVData";
 }
 
+/// Offset of a part's mesh triangles, stored as a count followed by three vertex indices for
+/// each triangle
 #[repr(C)]
 #[derive(Debug)]
-pub struct MeshIndex;
+pub struct MeshIndex(Ptr<u32>);
+
+unsafe impl BinaryStruct for MeshIndex {
+    const NAME: &'static str = "MeshIndex";
+}
+
+impl MeshIndex {
+    pub fn get_triangles<'a>(&self, binary: &'a [u8]) -> Result<&'a [UVec3], ParseError> {
+        let num_tri = *self.0.value(binary)?;
+        Ptr::<UVec3>::new((self.0.get_offset() + size_of::<u32>()) as u32).array(binary, num_tri as usize)
+    }
+}
 
 #[repr(C)]
 #[derive(Debug)]
@@ -807,6 +1462,23 @@ pub struct LabelEntry {
     time: u16
 }
 
+unsafe impl BinaryStruct for LabelEntry {
+    const NAME: &'static str = "LabelEntry";
+}
+
+unsafe impl BinaryStruct for Ptr<LabelEntry> {
+    const NAME: &'static str = "Ptr<LabelEntry>";
+}
+
+impl LabelEntry {
+    pub fn get_name<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.name.value(binary, "LabelEntry.name")
+    }
+    pub fn get_time(&self) -> u16 {
+        self.time
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct Anime {
@@ -821,73 +1493,206 @@ const _: () = {
     ["Size of Anime"][size_of::<Anime>() - 0x10];
 };
 
+unsafe impl BinaryStruct for Anime {
+    const NAME: &'static str = "Anime";
+}
+
 impl Anime {
-    pub fn get_parts(&self, binary: &[u8]) -> &[PartEntry] {
-        self.parts.array(binary, self.part_count as usize)
+    pub fn get_parts<'a>(&self, binary: &'a [u8], layout: &Layout) -> Result<Table<'a, PartEntry>, ParseError> {
+        self.parts.table(binary, self.part_count as usize, layout.part_entry_size)
     }
 
     pub fn get_num_parts(&self) -> u16 {
         self.part_count
     }
 
-    pub fn get_anims(&self, binary: &[u8]) -> &[AnimEntry] {
-        self.anims.array(binary, self.anim_count as usize)
+    pub fn get_anims<'a>(&self, binary: &'a [u8], layout: &Layout) -> Result<Table<'a, AnimEntry>, ParseError> {
+        self.anims.table(binary, self.anim_count as usize, layout.anim_entry_size)
     }
 
     pub fn get_num_anims(&self) -> u16 {
         self.anim_count
     }
 
-    pub fn get_name(&self, binary: &[u8]) -> &str {
-        self.name.value(binary)
+    pub fn get_name<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.name.value(binary, "Anime.name")
+    }
+
+    pub fn to_model(&self, file: &SsbpFile, cells: &HashMap<usize, (u16, &str)>,
+        texture_sizes: &HashMap<u16, Vec2>, warnings: &mut Vec<model::Warning>) -> std::io::Result<model::AnimePack> {
+        let (binary, layout) = (file.get_binary(), file.get_layout());
+        let parts = self.get_parts(binary, &layout)?;
+        let ref_ranges = self.get_ref_ranges(file, warnings)?;
+        let mut animations = Vec::with_capacity(self.anim_count as usize);
+        for anime in self.get_anims(binary, &layout)? {
+            animations.push(anime.to_model(file, parts, &ref_ranges, cells, texture_sizes, warnings)?);
+        }
+        let mut pack = model::AnimePack {
+            name: self.get_name(binary)?.to_string(),
+            parts: self.to_model_parts(binary, &layout)?,
+            animations
+        };
+        pack.infer_inheritance();
+        Ok(pack)
+    }
+
+    /// Get the (start, end) frames of the animation each instance part plays, for turning the
+    /// instance range back into label offsets. Warns once for each reference that can't be found.
+    pub fn get_ref_ranges(&self, file: &SsbpFile, warnings: &mut Vec<model::Warning>)
+        -> Result<Vec<Option<(u16, u16)>>, ParseError> {
+        let (binary, header) = (file.get_binary(), file.get_header());
+        let mut missing: Vec<&str> = vec![];
+        let mut ref_ranges = vec![];
+        for part in self.get_parts(binary, &file.get_layout())? {
+            if part._type != PartType::instance {
+                ref_ranges.push(None);
+                continue;
+            }
+            let ref_name = part.get_ref_name(binary)?;
+            let anim = header.find_animation(binary, ref_name)?;
+            if anim.is_none() && !missing.contains(&ref_name) {
+                warnings.push(model::Warning::MissingAnimation { anime_pack: self.get_name(binary)?.to_string(),
+                    part: part.get_name(binary)?.to_string(), ref_name: ref_name.to_string() });
+                missing.push(ref_name);
+            }
+            ref_ranges.push(anim.map(|a| (a.start_frames, a.end_frames)));
+        }
+        Ok(ref_ranges)
+    }
+
+    /// SSBP files don't keep bone settings, since bone deformation is baked into each frame's
+    /// mesh vertices. Bones are rebuilt from the pose in the setup animation (or the first
+    /// animation if there isn't one). Bone lengths aren't stored either, so each bone is guessed
+    /// to reach its first child bone, and bones without one get a length of 0.
+    fn to_model_parts(&self, binary: &[u8], layout: &Layout) -> Result<Vec<model::Part>, ParseError> {
+        let parts = self.get_parts(binary, layout)?;
+        let mut models = parts.iter().map(|p| p.to_model(binary)).collect::<Result<Vec<_>, _>>()?;
+        let anims = self.get_anims(binary, layout)?;
+        let pose_anim = match anims.iter().find(|a| a.get_name(binary).is_ok_and(|n| n == "Setup")) {
+            Some(a) => a, None => match anims.first() { Some(a) => a, None => return Ok(models) }
+        };
+        let pose = pose_anim.get_default_data(binary, parts.len(), layout)?;
+        let is_bone = |t: PartType| t == PartType::armature || t == PartType::bonepoint;
+        for (i, _) in parts.iter().enumerate().filter(|(_, p)| p._type == PartType::armature) {
+            let length = parts.iter().enumerate()
+                .find(|(_, c)| c.parent_index as usize == i && is_bone(c._type))
+                .map_or(0., |(c, _)| pose[c].position.truncate().length());
+            models[i].bone = Some(model::Bone {
+                length,
+                position: pose[i].position.truncate(),
+                rotation: pose[i].rotate.z
+            });
+        }
+        Ok(models)
+    }
+}
+
+/// State of a flag attribute on each frame of an animation, as written by the converter
+fn flag_states(animation: &model::Animation, part_name: &str, tag: &str) -> Vec<bool> {
+    let mut states = vec![false; animation.total_frames as usize];
+    let Some(attribute) = animation.part_animes.iter()
+        .find(|p| p.part_name == part_name)
+        .and_then(|p| p.attributes.iter().find(|a| a.tag == tag)) else {
+        return states;
+    };
+    for (i, key) in attribute.keyframes.iter().enumerate() {
+        let on = match key.value {
+            AttributeKeyframe::FlipH(v) | AttributeKeyframe::FlipV(v) | AttributeKeyframe::Hide(v) => v != 0,
+            _ => false
+        };
+        let end = attribute.keyframes.get(i + 1).map_or(states.len(), |k| k.frame.min(states.len()));
+        for state in states.iter_mut().take(end).skip(key.frame) {
+            *state = on;
+        }
+    }
+    states
+}
+
+impl model::AnimePack {
+    /// Recover each part's inheritance from the flip and hide flags in every animation, which the
+    /// converter resolves against the parent before they're written. A hidden parent always hides
+    /// a child that inherits it, so hiding is inherited wherever the frames allow it. A child that
+    /// matches its parent's flips could be inheriting them or flipping on its own, which the frames
+    /// can't tell apart. Its flip tracks are kept as they are, so a flip is only inherited when the
+    /// parent never flips and inheriting can't change the result.
+    fn infer_inheritance(&mut self) {
+        for i in 0..self.parts.len() {
+            let Some(parent) = usize::try_from(self.parts[i].parent_index).ok().and_then(|p| self.parts.get(p)) else {
+                continue;
+            };
+            let (name, parent_name) = (self.parts[i].name.clone(), parent.name.clone());
+            let (mut hide, mut flip_h, mut flip_v) = (true, true, true);
+            for animation in &self.animations {
+                let hidden = flag_states(animation, &name, "HIDE");
+                let parent_hidden = flag_states(animation, &parent_name, "HIDE");
+                hide &= parent_hidden.iter().zip(&hidden).all(|(p, c)| !p || *c);
+                flip_h &= !flag_states(animation, &parent_name, "FLPH").contains(&true);
+                flip_v &= !flag_states(animation, &parent_name, "FLPV").contains(&true);
+            }
+            self.parts[i].inheritance = model::Inheritance { flip_h, flip_v, hide, ..Default::default() };
+        }
     }
 
-    pub fn to_xml(&self, cell_names: &[String], binary: &[u8], cells: &HashMap<usize, (u16, &str)>) -> Result<Vec<u8>, Box<dyn Error>> {
+    pub fn to_xml(&self, cell_names: &[String]) -> Result<Vec<u8>, Box<dyn Error>> {
         let xml_fmt = "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n";
         let mut cursor = Cursor::new(xml_fmt.as_bytes().to_vec());
         cursor.seek(SeekFrom::End(0))?;
-        let mut writer = Writer::new_with_indent(cursor, '\t' as u8, 1);
+        let mut writer = Writer::new_with_indent(cursor, b'\t', 1);
         writer.create_element("SpriteStudioAnimePack")
             .with_attributes([("version", "2.00.01")])
-            .write_inner_content(|writer| self.to_xml_body(writer, cell_names, binary, cells))?;
+            .write_inner_content(|writer| self.to_xml_body(writer, cell_names))?;
         Ok(writer.into_inner().into_inner())
     }
 
     pub(crate) fn to_xml_body<W: Write + Seek>(&self,
-        writer: &mut Writer<W>, cell_names: &[String], binary: &[u8], cells: &HashMap<usize, (u16, &str)>) -> std::io::Result<()> {
+        writer: &mut Writer<W>, cell_names: &[String]) -> std::io::Result<()> {
+        let settings = self.get_settings();
         writer.create_element("settings")
-            .write_inner_content(|writer| to_xml_anime_settings(writer))?;
+            .write_inner_content(|writer| to_xml_anime_settings(writer, &settings))?;
         writer.create_element("name")
-            .write_text_content(BytesText::new(self.name.value(binary)))?;
-            create_blank_element(writer, "exportPath")?;
+            .write_text_content(BytesText::new(&self.name))?;
+        create_blank_element(writer, "exportPath")?;
         writer.create_element("Model")
-            .write_inner_content(|writer|  self.to_xml_model(writer, binary))?;
+            .write_inner_content(|writer|  self.to_xml_model(writer))?;
         create_name_list("cellmapNames", cell_names, writer)?;
         writer.create_element("animeList")
             .write_inner_content(|writer| {
-                for anime in self.get_anims(binary) {
+                for anime in &self.animations {
                     writer.create_element("anime")
-                        .write_inner_content(|writer| anime.to_xml(
-                            writer, binary, self.get_parts(binary), cells))?;
+                        .write_inner_content(|writer| anime.to_xml(writer, &settings))?;
                 }
                 Ok(())
             })?;
         Ok(())
     }
 
-    fn to_xml_model<W: Write + Seek>(&self, writer: &mut Writer<W>, binary: &[u8]) -> std::io::Result<()> {
+    fn to_xml_model<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("partList")
             .write_inner_content(|writer| {
-                for part in self.get_parts(binary) {
+                for part in &self.parts {
+                    let parent = usize::try_from(part.parent_index).ok().and_then(|i| self.parts.get(i));
                     writer.create_element("value")
-                        .write_inner_content(|writer| part.to_xml(writer, binary))?;
+                        .write_inner_content(|writer| part.to_xml(writer, parent))?;
                 }
                 Ok(())
             })?;
+        let bones: Vec<String> = self.parts.iter()
+            .filter(|p| p.bone.is_some()).map(|p| p.name.clone()).collect();
+        if !bones.is_empty() {
+            create_name_list("bonePartList", &bones, writer)?;
+        }
+        // bind weights aren't stored in SSBP, so no meshBind is written. Meshes are left unbound
+        // and keep the baked deformation from their DEFM keys.
         Ok(())
     }
 }
 
+/// Attribute tags in the order SpriteStudio lists them, which is also the order tracks are written in
+pub const AVAILABLE_ATTRIBUTES: [&str; 39] = ["CELL", "POSX", "POSY", "POSZ", "ROTX", "ROTY",
+    "ROTZ", "SCLX", "SCLY", "LSCX", "LSCY", "ALPH", "LALP", "PRIO", "IFLH", "IFLV", "FLPH", "FLPV", "HIDE",
+    "PCOL", "VCOL", "VERT", "PVTX", "PVTY", "ANCX", "ANCY", "SIZX", "SIZY", "UVTX", "UVTY", "UVRZ", "UVSX",
+    "UVSY", "BNDR", "MASK", "USER", "IPRM", "EFCT", "DEFM"];
+
 pub struct AttributeWriter<'a> {
     name: &'a str,
     attributes: HashMap<&'static str, Vec<(usize, AttributeKeyframe)>>,
@@ -916,24 +1721,44 @@ impl<'a> AttributeWriter<'a> {
         self.attributes.get_mut(value.get_tag_name()).as_mut().unwrap().push((frame, value));
     }
 
-    pub fn write_attributes<W: Write + Seek>(&self, writer: &mut Writer<W>, ignore_interpolation: bool) -> std::io::Result<()> {
-        for (tag, keyframes) in &self.attributes {
-            writer.create_element("attribute")
-                .with_attribute(("tag", *tag))
-                .write_inner_content(|writer| {
-                    for (frame, value) in keyframes {
-                        value.to_xml(*frame, writer, ignore_interpolation)?;
-                    }
-                    Ok(())
-                })?;
+    pub fn into_part_anime(self) -> model::PartAnime {
+        let mut attributes: Vec<model::Attribute> = self.attributes.into_iter()
+            .map(|(tag, keyframes)| model::Attribute {
+                tag: tag.to_string(),
+                keyframes: keyframes.into_iter()
+                    .map(|(frame, value)| model::Keyframe {
+                        frame,
+                        interpolation: match value.use_interpolation() {
+                            true => InterpolateType::linear, false => InterpolateType::none
+                        },
+                        curve: None,
+                        value
+                    })
+                    .collect()
+            }).collect();
+        // tracks are collected in a map, so order them the same way as SpriteStudio
+        attributes.sort_by_key(|a| AVAILABLE_ATTRIBUTES.iter().position(|t| *t == a.tag));
+        model::PartAnime {
+            part_name: self.name.to_string(),
+            attributes
         }
-        Ok(())
     }
 
-    pub(crate) fn attribute_to_xml_interp(&mut self,
-    last_keyframes: &mut HashMap<LowFlag, (usize, AttributeKeyframe)>, frame: usize,
-    flag: LowFlag, new: AttributeKeyframe) -> std::io::Result<()> {
-        let last = last_keyframes.get(&flag);
+    /// Remove tracks that only hold SpriteStudio's default value from the first frame. Size
+    /// defaults to the cell's size, and is ignored for parts without a cell.
+    pub fn remove_implicit_defaults(&mut self, cell_size: Option<Vec2>) {
+        self.attributes.retain(|_, keyframes| match keyframes.as_slice() {
+            [(0, AttributeKeyframe::SizeX(v))] => cell_size.is_some_and(|s| s.x != *v),
+            [(0, AttributeKeyframe::SizeY(v))] => cell_size.is_some_and(|s| s.y != *v),
+            [(0, value)] => !value.is_implicit_default(),
+            _ => true
+        });
+    }
+
+    pub(crate) fn add_attribute_interp(&mut self,
+    last_keyframes: &mut HashMap<&'static str, (usize, AttributeKeyframe)>, frame: usize,
+    new: AttributeKeyframe) -> std::io::Result<()> {
+        let last = last_keyframes.get(new.get_tag_name());
         let (allow_new, add_previous) = match last {
             Some((pf, v)) => {
                 match &new != v {
@@ -943,18 +1768,36 @@ impl<'a> AttributeWriter<'a> {
             },
             None => (true, false)
         };
-        if add_previous { // only called if there's at least one keyframe already
+        // values that aren't interpolated already hold until the next keyframe
+        if add_previous && new.use_interpolation() { // only called if there's at least one keyframe already
             self.add_attribute(frame - 1, last.unwrap().1.clone());
         }
         if allow_new {
-            last_keyframes.insert(flag, (frame, new.clone()));
+            last_keyframes.insert(new.get_tag_name(), (frame, new.clone()));
             self.add_attribute(frame, new);
         }
         Ok(())
     }
 }
 
+impl model::PartAnime {
+    pub fn write_attributes<W: Write + Seek>(&self, writer: &mut Writer<W>, ignore_interpolation: bool) -> std::io::Result<()> {
+        for attribute in &self.attributes {
+            writer.create_element("attribute")
+                .with_attribute(("tag", attribute.tag.as_str()))
+                .write_inner_content(|writer| {
+                    for keyframe in &attribute.keyframes {
+                        keyframe.to_xml(writer, ignore_interpolation)?;
+                    }
+                    Ok(())
+                })?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AttributePartsColorData {
     blend_type: BlendType,
     rgba: u32,
@@ -972,6 +1815,7 @@ impl AttributePartsColorData {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AttributePartsColor {
     One(AttributePartsColorData),
     Vertex([AttributePartsColorData; 4])
@@ -1011,7 +1855,142 @@ impl AttributePartsColor {
     }
 }
 
+/// Offsets applied to each corner of a part's quad
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AttributeVertexTransform {
+    lt: Vec2,
+    rt: Vec2,
+    lb: Vec2,
+    rb: Vec2
+}
+
+impl AttributeVertexTransform {
+    /// Read the offsets of each corner set in `flags`. Corners that aren't set aren't moved.
+    pub fn read(data: &mut FramePart, binary: &[u8], flags: ColorAttributeFlags) -> Result<Self, ParseError> {
+        let mut corners = [Vec2::ZERO; 4];
+        for (i, flag) in [ColorAttributeFlags::VERTEX_FLAG_LT, ColorAttributeFlags::VERTEX_FLAG_RT,
+            ColorAttributeFlags::VERTEX_FLAG_LB, ColorAttributeFlags::VERTEX_FLAG_RB].into_iter().enumerate() {
+            if flags.contains(flag) {
+                corners[i] = Vec2::new(data.read::<f32>(binary)?, data.read::<f32>(binary)?);
+            }
+        }
+        Ok(Self { lt: corners[0], rt: corners[1], lb: corners[2], rb: corners[3] })
+    }
+    pub fn get_lt(&self) -> Vec2 {
+        self.lt
+    }
+    pub fn get_rt(&self) -> Vec2 {
+        self.rt
+    }
+    pub fn get_lb(&self) -> Vec2 {
+        self.lb
+    }
+    pub fn get_rb(&self) -> Vec2 {
+        self.rb
+    }
+
+    fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
+        for (name, corner) in [("LT", self.lt), ("RT", self.rt), ("LB", self.lb), ("RB", self.rb)] {
+            writer.create_element(name)
+                .write_text_content(BytesText::new(&format!("{} {}", corner.x, corner.y)))?;
+        }
+        Ok(())
+    }
+}
+
+/// Offset of each mesh vertex from its position in the cell
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AttributeDeform {
+    offsets: Vec<Vec2>
+}
+
+impl AttributeDeform {
+    pub fn new(rest: &[Vec2], vertices: &[Vec3]) -> Self {
+        Self { offsets: rest.iter().zip(vertices).map(|(r, v)| v.truncate() - *r).collect() }
+    }
+    pub fn get_offsets(&self) -> &[Vec2] {
+        &self.offsets
+    }
+
+    fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
+        writer.create_element("vsize")
+            .write_text_content(BytesText::new(&format!("{}", self.offsets.len())))?;
+        // only moved vertices are listed, prefixed by how many there are
+        let moved: Vec<_> = self.offsets.iter().enumerate().filter(|(_, o)| **o != Vec2::ZERO).collect();
+        let mut changes = format!("{}", moved.len());
+        for (i, offset) in moved {
+            changes.push_str(&format!(" {} {} {}", i, offset.x, offset.y));
+        }
+        writer.create_element("vchg")
+            .write_text_content(BytesText::new(&changes))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AttributeUserData {
+    integer: Option<UserDataInteger>,
+    rect: Option<UserDataRect>,
+    point: Option<UserDataPoint>,
+    string: Option<String>
+}
+
+impl AttributeUserData {
+    /// Read the values that follow a user data entry's flags, in flag order
+    pub fn read(data: &mut FramePart, binary: &[u8], flags: UserDataFlags) -> Result<Self, ParseError> {
+        let integer = match flags.contains(UserDataFlags::INTEGER) {
+            true => Some(data.read::<UserDataInteger>(binary)?), false => None
+        };
+        let rect = match flags.contains(UserDataFlags::RECT) {
+            true => Some(data.read::<UserDataRect>(binary)?), false => None
+        };
+        let point = match flags.contains(UserDataFlags::POINT) {
+            true => Some(data.read::<UserDataPoint>(binary)?), false => None
+        };
+        let string = match flags.contains(UserDataFlags::STRING) {
+            true => Some(data.read::<UserDataString>(binary)?.get_string(binary)?.to_string()), false => None
+        };
+        Ok(Self { integer, rect, point, string })
+    }
+    pub fn get_integer(&self) -> Option<&UserDataInteger> {
+        self.integer.as_ref()
+    }
+    pub fn get_rect(&self) -> Option<&UserDataRect> {
+        self.rect.as_ref()
+    }
+    pub fn get_point(&self) -> Option<&UserDataPoint> {
+        self.point.as_ref()
+    }
+    pub fn get_string(&self) -> Option<&str> {
+        self.string.as_deref()
+    }
+
+    fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
+        if let Some(v) = &self.integer {
+            writer.create_element("integer")
+                .write_text_content(BytesText::new(&format!("{}", v.integer)))?;
+        }
+        if let Some(v) = &self.rect {
+            writer.create_element("rect")
+                .write_text_content(BytesText::new(&format!("{} {} {} {}", v.left, v.top, v.right, v.bottom)))?;
+        }
+        if let Some(v) = &self.point {
+            writer.create_element("point")
+                .write_text_content(BytesText::new(&format!("{} {}", v.x, v.y)))?;
+        }
+        if let Some(v) = &self.string {
+            writer.create_element("string")
+                .write_text_content(BytesText::new(v))?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AttributeKeyframe {
     Cell((u16, String)),
     PositionX(f32),
@@ -1042,8 +2021,11 @@ pub enum AttributeKeyframe {
     FlipV(u16),
     Hide(u16),
     PartsColor(AttributePartsColor),
+    VertexTransform(AttributeVertexTransform),
+    Deform(AttributeDeform),
     EffectKeyframe(EffectKeyframe),
-    InstanceKeyframe(InstanceKeyframe)
+    InstanceKeyframe(AttributeInstance),
+    User(AttributeUserData)
 }
 
 impl AttributeKeyframe {
@@ -1078,146 +2060,321 @@ impl AttributeKeyframe {
             Self::FlipV(_) => "FLPV",
             Self::Hide(_) => "HIDE",
             Self::PartsColor(_) => "PCOL",
+            Self::VertexTransform(_) => "VERT",
+            Self::Deform(_) => "DEFM",
             Self::EffectKeyframe(_) => "EFCT",
-            Self::InstanceKeyframe(_) => "IPRM"
+            Self::InstanceKeyframe(_) => "IPRM",
+            Self::User(_) => "USER"
+        }
+    }
+
+    /// Whether SpriteStudio uses this value for an attribute without any keyframes
+    fn is_implicit_default(&self) -> bool {
+        match self {
+            Self::ScaleX(v) | Self::ScaleY(v) | Self::LocalScaleX(v) | Self::LocalScaleY(v)
+            | Self::Opacity(v) | Self::LocalOpacity(v) | Self::UVScaleU(v) | Self::UVScaleV(v) => *v == 1.,
+            Self::Mask(v) | Self::Prio(v) => *v == 0,
+            Self::Cell(_) | Self::PartsColor(_) | Self::VertexTransform(_) | Self::Deform(_)
+            | Self::EffectKeyframe(_) | Self::InstanceKeyframe(_) | Self::User(_)
+            | Self::FlipH(_) | Self::FlipV(_) | Self::Hide(_) => false,
+            _ => self.get_scalar() == Some(0.)
         }
     }
 
     fn use_interpolation(&self) -> bool {
+        !matches!(self, Self::Cell(_) | Self::FlipH(_) | Self::FlipV(_) | Self::Hide(_)
+            | Self::EffectKeyframe(_) | Self::InstanceKeyframe(_) | Self::User(_))
+    }
+
+    /// Get the value of attributes that hold a single number
+    pub fn get_scalar(&self) -> Option<f32> {
         match self {
-            Self::Cell(_) | Self::FlipH(_) | Self::FlipV(_) | Self::Hide(_)
-            | Self::EffectKeyframe(_) | Self::InstanceKeyframe(_) => false,
-            _ => true
+            Self::PositionX(v) | Self::PositionY(v) | Self::PositionZ(v)
+            | Self::PivotX(v) | Self::PivotY(v)
+            | Self::RotationX(v) | Self::RotationY(v) | Self::RotationZ(v)
+            | Self::ScaleX(v) | Self::ScaleY(v) | Self::LocalScaleX(v) | Self::LocalScaleY(v)
+            | Self::Opacity(v) | Self::LocalOpacity(v) | Self::SizeX(v) | Self::SizeY(v)
+            | Self::UVMoveU(v) | Self::UVMoveV(v) | Self::UVRotate(v) | Self::UVScaleU(v) | Self::UVScaleV(v)
+            | Self::BoundingRadius(v) => Some(*v),
+            _ => None
         }
     }
 
-    fn into_xml_attributes<'a>(&'a self, frame_str: &'a str, ignore_interpolation: bool) -> Vec<Attribute> {
+    /// Create a keyframe of the same attribute holding a different number
+    pub fn with_scalar(&self, v: f32) -> Option<Self> {
+        match self {
+            Self::PositionX(_) => Some(Self::PositionX(v)),
+            Self::PositionY(_) => Some(Self::PositionY(v)),
+            Self::PositionZ(_) => Some(Self::PositionZ(v)),
+            Self::PivotX(_) => Some(Self::PivotX(v)),
+            Self::PivotY(_) => Some(Self::PivotY(v)),
+            Self::RotationX(_) => Some(Self::RotationX(v)),
+            Self::RotationY(_) => Some(Self::RotationY(v)),
+            Self::RotationZ(_) => Some(Self::RotationZ(v)),
+            Self::ScaleX(_) => Some(Self::ScaleX(v)),
+            Self::ScaleY(_) => Some(Self::ScaleY(v)),
+            Self::LocalScaleX(_) => Some(Self::LocalScaleX(v)),
+            Self::LocalScaleY(_) => Some(Self::LocalScaleY(v)),
+            Self::Opacity(_) => Some(Self::Opacity(v)),
+            Self::LocalOpacity(_) => Some(Self::LocalOpacity(v)),
+            Self::SizeX(_) => Some(Self::SizeX(v)),
+            Self::SizeY(_) => Some(Self::SizeY(v)),
+            Self::UVMoveU(_) => Some(Self::UVMoveU(v)),
+            Self::UVMoveV(_) => Some(Self::UVMoveV(v)),
+            Self::UVRotate(_) => Some(Self::UVRotate(v)),
+            Self::UVScaleU(_) => Some(Self::UVScaleU(v)),
+            Self::UVScaleV(_) => Some(Self::UVScaleV(v)),
+            Self::BoundingRadius(_) => Some(Self::BoundingRadius(v)),
+            _ => None
+        }
+    }
+
+    fn value_to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
+        let value = writer.create_element("value");
+        match self {
+            Self::Cell((map_id, name)) => {
+                value.write_inner_content(|writer| {
+                    writer.create_element("mapId")
+                        .write_text_content(BytesText::new(&format!("{}", map_id)))?;
+                    writer.create_element("name")
+                        .write_text_content(BytesText::new(name))?;
+                    Ok(())
+                })
+            },
+            Self::PositionX(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::PositionY(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::PositionZ(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::PivotX(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::PivotY(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::RotationX(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::RotationY(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::RotationZ(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::ScaleX(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::ScaleY(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::LocalScaleX(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::LocalScaleY(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::Opacity(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::LocalOpacity(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::SizeX(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::SizeY(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::UVMoveU(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::UVMoveV(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::UVRotate(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::UVScaleU(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::UVScaleV(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::BoundingRadius(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::Mask(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::Prio(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::FlipH(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::FlipV(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::Hide(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
+            Self::PartsColor(p) => match p {
+                AttributePartsColor::One(one) => {
+                    value.write_inner_content(|writer| {
+                        writer.create_element("target")
+                            .write_text_content(BytesText::new("whole"))?;
+                        writer.create_element("blendType")
+                            .write_text_content(BytesText::new(&format!("{:?}", one.blend_type)))?;
+                        writer.create_element("color")
+                            .write_inner_content(|writer| one.to_xml(writer))?;
+                        Ok(())
+                    })
+                },
+                AttributePartsColor::Vertex(v) => {
+                    value.write_inner_content(|writer| {
+                        writer.create_element("target")
+                            .write_text_content(BytesText::new("vertex"))?;
+                        writer.create_element("blendType")
+                            .write_text_content(BytesText::new(&format!("{:?}", v[0].blend_type)))?;
+                        writer.create_element("LT")
+                            .write_inner_content(|writer| v[0].to_xml(writer))?;
+                        writer.create_element("RT")
+                            .write_inner_content(|writer| v[1].to_xml(writer))?;
+                        writer.create_element("LB")
+                            .write_inner_content(|writer| v[2].to_xml(writer))?;
+                        writer.create_element("RB")
+                            .write_inner_content(|writer| v[3].to_xml(writer))?;
+                        Ok(())
+                    })
+                }
+            },
+            Self::VertexTransform(v) => value.write_inner_content(|writer| v.to_xml(writer)),
+            Self::Deform(d) => value.write_inner_content(|writer| d.to_xml(writer)),
+            Self::EffectKeyframe(k) => {
+                value.write_inner_content(|writer| {
+                    writer.create_element("startTime")
+                        .write_text_content(BytesText::new(&format!("{}", k.get_start_time())))?;
+                    writer.create_element("speed")
+                        .write_text_content(BytesText::new(&format!("{}", k.get_speed())))?;
+                    writer.create_element("independent")
+                        .write_text_content(BytesText::new(&format!("{}", k.get_loop_flag().contains(EffectLoopFlags::INFINITY) as u8)))?;
+                    Ok(())
+                })
+            },
+            Self::InstanceKeyframe(k) => value.write_inner_content(|writer| k.to_xml(writer)),
+            Self::User(u) => value.write_inner_content(|writer| u.to_xml(writer)),
+        }?;
+        Ok(())
+    }
+}
+
+impl model::Keyframe {
+    fn to_xml_attributes<'a>(&self, frame_str: &'a str, ip_type_str: &'a str, ignore_interpolation: bool) -> Vec<Attribute<'a>> {
         let mut attributes = vec![];
         attributes.push(Attribute::from(("time", frame_str)));
-        if self.use_interpolation() && !ignore_interpolation {
-            attributes.push(Attribute::from(("ipType", "linear")));
+        if self.interpolation != InterpolateType::none && !ignore_interpolation {
+            attributes.push(Attribute::from(("ipType", ip_type_str)));
         }
         attributes
     }
 
-    fn to_xml<W: Write + Seek>(&self, frame: usize, writer: &mut Writer<W>, ignore_interpolation: bool)
-        -> std::io::Result<()> {
-        let frame_as_str = format!("{}", frame);
+    fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>, ignore_interpolation: bool) -> std::io::Result<()> {
+        let frame_as_str = format!("{}", self.frame);
+        let ip_type_as_str = format!("{:?}", self.interpolation);
         writer.create_element("key")
-            .with_attributes(self.into_xml_attributes(&frame_as_str, ignore_interpolation))
+            .with_attributes(self.to_xml_attributes(&frame_as_str, &ip_type_as_str, ignore_interpolation))
             .write_inner_content(|writer| {
-                let value = writer.create_element("value");
-                match self {
-                    Self::Cell((map_id, name)) => {
-                        value.write_inner_content(|writer| {
-                            writer.create_element("mapId")
-                                .write_text_content(BytesText::new(&format!("{}", map_id)))?;
-                            writer.create_element("name")
-                                .write_text_content(BytesText::new(&name))?;
-                            Ok(())
-                        })
-                    },
-                    Self::PositionX(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::PositionY(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::PositionZ(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::PivotX(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::PivotY(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::RotationX(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::RotationY(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::RotationZ(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::ScaleX(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::ScaleY(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::LocalScaleX(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::LocalScaleY(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::Opacity(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::LocalOpacity(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::SizeX(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::SizeY(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::UVMoveU(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::UVMoveV(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::UVRotate(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::UVScaleU(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::UVScaleV(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::BoundingRadius(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::Mask(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::Prio(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::FlipH(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::FlipV(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::Hide(v) => value.write_text_content(BytesText::new(&format!("{}", *v))),
-                    Self::PartsColor(p) => match p {
-                        AttributePartsColor::One(one) => {
-                            value.write_inner_content(|writer| {
-                                writer.create_element("target")
-                                    .write_text_content(BytesText::new("whole"))?;
-                                writer.create_element("blendType")
-                                    .write_text_content(BytesText::new(&format!("{:?}", one.blend_type)))?;
-                                writer.create_element("color")
-                                    .write_inner_content(|writer| one.to_xml(writer))?;
-                                Ok(())
-                            })
-                        },
-                        AttributePartsColor::Vertex(v) => {
-                            value.write_inner_content(|writer| {
-                                writer.create_element("target")
-                                    .write_text_content(BytesText::new("vertex"))?;
-                                writer.create_element("blendType")
-                                    .write_text_content(BytesText::new(&format!("{:?}", v[0].blend_type)))?;
-                                writer.create_element("LT")
-                                    .write_inner_content(|writer| v[0].to_xml(writer))?;
-                                writer.create_element("RT")
-                                    .write_inner_content(|writer| v[1].to_xml(writer))?;
-                                writer.create_element("LB")
-                                    .write_inner_content(|writer| v[2].to_xml(writer))?;
-                                writer.create_element("RB")
-                                    .write_inner_content(|writer| v[3].to_xml(writer))?;
-                                Ok(())
-                            })
-                        }
-                    },
-                    Self::EffectKeyframe(k) => {
-                        value.write_inner_content(|writer| {
-                            writer.create_element("startTime")
-                                .write_text_content(BytesText::new(&format!("{}", unsafe { std::ptr::read(&raw const k.start_time) })))?;
-                            writer.create_element("speed")
-                                .write_text_content(BytesText::new(&format!("{}", unsafe { std::ptr::read(&raw const k.speed) })))?;
-                            writer.create_element("independent")
-                                .write_text_content(BytesText::new("0"))?;
-                            Ok(())
-                        })
-                    },
-                    Self::InstanceKeyframe(k) => {
-                        value.write_inner_content(|writer| {
-                            let loop_flags = unsafe { std::ptr::read(&raw const k.loop_flag) };
-                            if loop_flags.contains(InstanceLoopFlags::INFINITY) {
-                                writer.create_element("infinity")
-                                    .write_text_content(BytesText::new("1"))?;
-                            }
-                            if loop_flags.contains(InstanceLoopFlags::REVERSE) {
-                                writer.create_element("reverse")
-                                    .write_text_content(BytesText::new("1"))?;
-                            }
-                            if loop_flags.contains(InstanceLoopFlags::PINGPONG) {
-                                writer.create_element("pingpong")
-                                    .write_text_content(BytesText::new("1"))?;
-                            }
-                            if loop_flags.contains(InstanceLoopFlags::INDEPENDENT) {
-                                writer.create_element("independent")
-                                    .write_text_content(BytesText::new("1"))?;
-                            }
-                            writer.create_element("loopNum")
-                                .write_text_content(BytesText::new(&format!("{}", unsafe { std::ptr::read(&raw const k.loop_num) })))?;
-                            writer.create_element("startOffset")
-                                .write_text_content(BytesText::new(&format!("{}", unsafe { std::ptr::read(&raw const k.start_frame) })))?;
-                            writer.create_element("endOffset")
-                                .write_text_content(BytesText::new(&format!("{}", unsafe { std::ptr::read(&raw const k.end_frame) })))?;
-                            writer.create_element("speed")
-                                .write_text_content(BytesText::new(&format!("{}", unsafe { std::ptr::read(&raw const k.speed) })))?;
-                            Ok(())
-                        })
-                    },
-                }?;
-                Ok(())
+                if let Some(c) = self.curve.as_ref().filter(|_| !ignore_interpolation) {
+                    writer.create_element("curve")
+                        .write_text_content(BytesText::new(&format!("{} {} {} {}",
+                            c.start_time, c.start_value, c.end_time, c.end_value)))?;
+                }
+                self.value.value_to_xml(writer)
             })?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use quick_xml::Writer;
+    use crate::test_data::{anime_pack_model, part_entry_offset, part_frames, read_u32, write_anime_project, TestFramePart, TestPart};
+    use crate::model::Warning;
+    use super::{flag_states, AttributeKeyframe, EffectLoopFlags, LowFlag, PartType};
+
+    fn parts() -> [TestPart; 2] {
+        [
+            TestPart { name: "root", parent: -1, position_x: 5. },
+            TestPart { name: "child", parent: 0, position_x: 7. },
+        ]
+    }
+
+    #[test]
+    fn frame_parts_use_their_stored_index() {
+        // the child is drawn first, so it's stored before the root
+        let binary = write_anime_project(&parts(), &[vec![
+            TestFramePart { index: 1, low_flag: LowFlag::PART_FLAG_POSITION_X.bits(), values: vec![20.] },
+            TestFramePart { index: 0, low_flag: 0, values: vec![] },
+        ]]);
+        let frames: Vec<_> = part_frames(&binary, 2).into_iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].part, frames[0].state.position.x), (1, 20.));
+        // the root isn't stored, so it keeps its own initial value
+        assert_eq!((frames[1].part, frames[1].state.position.x), (0, 5.));
+    }
+
+    #[test]
+    fn animation_keys_go_to_the_stored_part() {
+        let binary = write_anime_project(&parts(), &[vec![
+            TestFramePart { index: 1, low_flag: LowFlag::PART_FLAG_POSITION_X.bits(), values: vec![20.] },
+            TestFramePart { index: 0, low_flag: 0, values: vec![] },
+        ]]);
+        let pack = anime_pack_model(&binary, &mut vec![]);
+        let position_x = |name: &str| pack.animations[0].part_animes.iter()
+            .find(|p| p.part_name == name)
+            .and_then(|p| p.attributes.iter().find(|a| a.tag == "POSX"))
+            .map(|a| a.keyframes[0].value.clone());
+        assert_eq!(position_x("root"), Some(AttributeKeyframe::PositionX(5.)));
+        assert_eq!(position_x("child"), Some(AttributeKeyframe::PositionX(20.)));
+    }
+
+    #[test]
+    fn missing_cells_are_warnings() {
+        // the cell index is a u16, so only the low half of the value is read
+        let binary = write_anime_project(&parts()[..1], &[vec![
+            TestFramePart { index: 0, low_flag: LowFlag::PART_FLAG_CELL_INDEX.bits(), values: vec![f32::from_bits(7)] },
+        ]]);
+        let mut warnings = vec![];
+        anime_pack_model(&binary, &mut warnings);
+        assert_eq!(warnings, [Warning::MissingCell { animation: "anim".to_string(), part: "root".to_string(),
+            frame: 0, cell_index: 7 }]);
+    }
+
+    #[test]
+    fn missing_instance_animations_are_warned_about_once() {
+        let mut binary = write_anime_project(&parts(), &[vec![
+            TestFramePart { index: 0, low_flag: 0, values: vec![] },
+            TestFramePart { index: 1, low_flag: 0, values: vec![] },
+        ]]);
+        let pack_name = read_u32(binary.bytes(), read_u32(binary.bytes(), 0x14)) as u32;
+        // make both parts instances of "pack", which has no animation name
+        for i in 0..2 {
+            let part = part_entry_offset(binary.bytes(), i);
+            let bytes = binary.bytes_mut();
+            bytes[part + 8..part + 10].copy_from_slice(&(PartType::instance as u16).to_le_bytes());
+            bytes[part + 16..part + 20].copy_from_slice(&pack_name.to_le_bytes());
+        }
+        let mut warnings = vec![];
+        let pack = anime_pack_model(&binary, &mut warnings);
+        assert_eq!(warnings, [Warning::MissingAnimation { anime_pack: "pack".to_string(), part: "root".to_string(),
+            ref_name: "pack".to_string() }]);
+        // the instance keys are still written, counting from the first frame
+        assert!(pack.animations[0].part_animes.iter().all(|p| p.attributes.iter().any(|a| a.tag == "IPRM")));
+    }
+
+    #[test]
+    fn effect_loop_flags_round_trip() {
+        // bit 0 is the only loop flag, and it's written as independent
+        for (flags, independent) in [(0, 0), (EffectLoopFlags::INFINITY.bits(), 1), (1 << 1, 0)] {
+            // current frame, start time, speed and loop flags
+            let values = [5, 2, 1.5f32.to_bits(), flags].map(f32::from_bits).to_vec();
+            let mut binary = write_anime_project(&parts()[..1], &[vec![
+                TestFramePart { index: 0, low_flag: LowFlag::PART_FLAG_EFFECT_KEYFRAME.bits(), values },
+            ]]);
+            let part = part_entry_offset(binary.bytes(), 0);
+            binary.bytes_mut()[part + 8..part + 10].copy_from_slice(&(PartType::effect as u16).to_le_bytes());
+            let pack = anime_pack_model(&binary, &mut vec![]);
+            let key = &pack.animations[0].part_animes[0].attributes.iter()
+                .find(|a| a.tag == "EFCT").unwrap().keyframes[0];
+            let AttributeKeyframe::EffectKeyframe(effect) = &key.value else { panic!("{:?}", key.value) };
+            assert_eq!(effect.get_loop_flag().bits(), flags);
+            // the key is placed on the frame the effect starts, apart from where it starts playing
+            assert_eq!((key.frame, effect.get_start_time(), effect.get_speed()), (5, 2, 1.5));
+            let mut writer = Writer::new(Cursor::new(vec![]));
+            key.value.value_to_xml(&mut writer).unwrap();
+            let xml = String::from_utf8(writer.into_inner().into_inner()).unwrap();
+            assert_eq!(xml, format!("<value><startTime>2</startTime><speed>1.5</speed>\
+                <independent>{}</independent></value>", independent));
+        }
+    }
+
+    #[test]
+    fn independent_child_flips_are_kept() {
+        let flip = LowFlag::PART_FLAG_FLIP_H.bits();
+        // the child flips on both frames by itself, while the parent flips on one or both of them
+        for parent_flips in [[true, false], [true, true]] {
+            let binary = write_anime_project(&parts(), &parent_flips.map(|parent| vec![
+                TestFramePart { index: 0, low_flag: if parent { flip } else { 0 }, values: vec![] },
+                TestFramePart { index: 1, low_flag: flip, values: vec![] },
+            ]));
+            let pack = anime_pack_model(&binary, &mut vec![]);
+            assert_eq!(flag_states(&pack.animations[0], "root", "FLPH"), parent_flips);
+            assert_eq!(flag_states(&pack.animations[0], "child", "FLPH"), [true, true]);
+            let child = &pack.parts[1].inheritance;
+            assert_eq!((child.flip_h, child.flip_v, child.image_flip_h), (false, true, false));
+        }
+    }
+
+    #[test]
+    fn frame_part_index_out_of_range_is_an_error() {
+        let binary = write_anime_project(&parts(), &[vec![
+            TestFramePart { index: 2, low_flag: 0, values: vec![] },
+            TestFramePart { index: 0, low_flag: 0, values: vec![] },
+        ]]);
+        // decoding stops at the bad entry
+        let frames = part_frames(&binary, 2);
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].is_err());
+    }
+}
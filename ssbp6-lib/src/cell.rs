@@ -6,14 +6,16 @@ use std::io::{Cursor, Seek, SeekFrom, Write};
 use glam::{UVec2, Vec2};
 use quick_xml::events::BytesText;
 use quick_xml::Writer;
-use crate::util::{Ptr, StringPtr};
+use crate::model;
+use crate::util::{check_enum, BinaryStruct, ParseError, Ptr, StringPtr};
 
 #[derive(Debug)]
 pub struct CastError((&'static str, usize));
 impl Error for CastError {}
 impl Display for CastError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        <Self as Debug>::fmt(self, f)
+        let (name, value) = self.0;
+        write!(f, "{} has invalid value {}", name, value)
     }
 }
 impl CastError {
@@ -24,6 +26,7 @@ impl CastError {
 
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(non_camel_case_types)]
 pub enum InterpolateType {
     none,
@@ -38,7 +41,7 @@ impl TryFrom<u32> for InterpolateType {
     type Error = CastError;
     fn try_from(value: u32) -> Result<Self, Self::Error> {
         if value <= Self::deceleration as u32 {
-            Ok(unsafe { std::mem::transmute(value)})
+            Ok(unsafe { std::mem::transmute::<u32, Self>(value) })
         } else {
             Err(CastError(("InterpolateType", value as usize)))
         }
@@ -47,6 +50,7 @@ impl TryFrom<u32> for InterpolateType {
 
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(non_camel_case_types)]
 pub enum TexWrapMode {
     clamp,
@@ -56,6 +60,7 @@ pub enum TexWrapMode {
 
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[allow(non_camel_case_types)]
 pub enum TexFilterMode {
     nearlest,
@@ -84,8 +89,12 @@ const _: () = {
     ["Size of CellEntry"][size_of::<CellEntry>() - 0x2c];
 };
 
+unsafe impl BinaryStruct for CellEntry {
+    const NAME: &'static str = "CellEntry";
+}
+
 impl CellEntry {
-    pub fn get_cell_map(&self, binary: &[u8]) -> &CellMap {
+    pub fn get_cell_map<'a>(&self, binary: &'a [u8]) -> Result<&'a CellMap, ParseError> {
         self.cell_map.value(binary)
     }
 }
@@ -106,7 +115,7 @@ impl Hash for CellEntry {
 
 impl PartialOrd for CellEntry {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.index.partial_cmp(&other.index)
+        Some(self.cmp(other))
     }
 }
 
@@ -117,8 +126,8 @@ impl Ord for CellEntry {
 }
 
 impl CellEntry {
-    pub fn get_name(&self, binary: &[u8]) -> &str {
-        self.name.value(binary)
+    pub fn get_name<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.name.value(binary, "CellEntry.name")
     }
     pub fn get_index(&self) -> u16 {
         self.index
@@ -132,33 +141,148 @@ impl CellEntry {
     pub fn get_pivot_x(&self) -> f32 {
         self.pivot_x
     }
-    pub fn get_pivot(&self) -> Vec2 {
-        Vec2::new(self.pivot_x, self.pivot_y)
+    /// Pivot relative to the cell's center as a fraction of its size, with Y pointing down as in
+    /// [`CellEntry::point_to_local`]. Rotated cells are assumed to store their pivot in the
+    /// orientation of the atlas, where the cell's top left is in the top right of its region, so
+    /// a point (x, y) in the region is (y, -x) in the cell. This keeps the pivot on the same texel
+    /// as [`CellEntry::uv_to_point`].
+    ///
+    /// The assumption hasn't been checked against a real atlas with rotated cells, only against
+    /// hand-made test data. If the converter already stores the pivot in the cell's orientation,
+    /// rotated cells get the wrong pivot.
+    pub fn get_pivot(&self, rotated: bool) -> Vec2 {
+        match rotated {
+            true => Vec2::new(self.pivot_y, -self.pivot_x),
+            false => Vec2::new(self.pivot_x, self.pivot_y)
+        }
     }
     pub fn get_texcoord(&self) -> [Vec2; 2] {
         [Vec2::new(self.u1, self.v1), Vec2::new(self.u2, self.v2)]
     }
+    /// Whether the cell is stored rotated 90° clockwise in a texture of the given size, which is
+    /// the case when the texture coordinates cover a region with the cell's width and height
+    /// swapped. See [`infer_texture_size`] for getting the size.
+    ///
+    /// SSBP has no rotation flag, so rotation is inferred, and the inference has only been checked
+    /// against hand-made test data, not against a real packed atlas. It assumes that the packer
+    /// only rotates clockwise. A square cell (or one whose sides differ by less than a pixel) is
+    /// never detected as rotated, because its region looks the same either way.
+    pub fn is_rotated_in(&self, texture_size: Vec2) -> bool {
+        let [min, max] = self.get_texcoord();
+        let region = (max - min) * texture_size;
+        let size = self.get_size().as_vec2();
+        region.abs_diff_eq(Vec2::new(size.y, size.x), 1.) && !region.abs_diff_eq(size, 1.)
+    }
+    /// Convert a texture coordinate into pixels from the top left of the cell
+    pub fn uv_to_point(&self, uv: Vec2, rotated: bool) -> Vec2 {
+        let [min, max] = self.get_texcoord();
+        let range = max - min;
+        let range = Vec2::select(range.cmpeq(Vec2::ZERO), Vec2::ONE, range);
+        let point = (uv - min) / range;
+        // the cell's top left is in the top right of its region in the atlas
+        let point = match rotated {
+            true => Vec2::new(point.y, 1. - point.x),
+            false => point
+        };
+        point * self.get_size().as_vec2()
+    }
+    /// Convert pixels from the top left of the cell into the part's coordinates, which have the
+    /// pivot as the origin and Y pointing up
+    pub fn point_to_local(&self, point: Vec2, rotated: bool) -> Vec2 {
+        let size = self.get_size().as_vec2();
+        let pivot = self.get_pivot(rotated);
+        Vec2::new(point.x - size.x * (0.5 + pivot.x), size.y * (0.5 + pivot.y) - point.y)
+    }
+
+    /// `texture_size` is the size of the cell map's texture if it's known, for detecting rotation
+    pub fn to_model(&self, binary: &[u8], texture_size: Option<Vec2>) -> Result<model::Cell, ParseError> {
+        let rotated = texture_size.is_some_and(|size| self.is_rotated_in(size));
+        Ok(model::Cell {
+            name: self.get_name(binary)?.to_string(),
+            index: self.index,
+            position: self.get_pos(),
+            size: self.get_size(),
+            pivot: self.get_pivot(rotated),
+            rotated,
+            texcoord: self.get_texcoord(),
+            mesh: None,
+        })
+    }
+}
 
-    pub fn to_xml<W: Write>(&self, writer: &mut Writer<W>, binary: &[u8]) -> std::io::Result<()> {
+/// Size of a cell map's texture, worked out from the cells in it (SSBP doesn't store it). `None`
+/// if every cell is in the top left corner of the texture
+pub fn infer_texture_size(cells: &[&CellEntry]) -> Option<Vec2> {
+    // cells that don't touch the top or left edge give the size directly
+    let mut width = cells.iter().find(|c| c.u1 > 0.).map(|c| (c.x as f32 / c.u1).round());
+    let mut height = cells.iter().find(|c| c.v1 > 0.).map(|c| (c.y as f32 / c.v1).round());
+    // otherwise, the known side tells whether a cell is rotated, which gives the other side
+    if let (Some(w), None) = (width, height) {
+        height = cells.iter().filter(|c| c.v2 > c.v1).find_map(|c| {
+            let region = (c.u2 - c.u1) * w;
+            let side = match (region - c.width as f32).abs() < 1. {
+                true => c.height,
+                false if (region - c.height as f32).abs() < 1. => c.width,
+                false => return None
+            };
+            Some((side as f32 / (c.v2 - c.v1)).round())
+        });
+    }
+    if let (None, Some(h)) = (width, height) {
+        width = cells.iter().filter(|c| c.u2 > c.u1).find_map(|c| {
+            let region = (c.v2 - c.v1) * h;
+            let side = match (region - c.height as f32).abs() < 1. {
+                true => c.width,
+                false if (region - c.width as f32).abs() < 1. => c.height,
+                false => return None
+            };
+            Some((side as f32 / (c.u2 - c.u1)).round())
+        });
+    }
+    width.zip(height).map(|(w, h)| Vec2::new(w, h))
+}
+
+impl model::Cell {
+    pub fn to_xml<W: Write>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("cell")
             .write_inner_content(|writer| {
-                let cell_name = self.name.value(binary);
-                writer.create_element("name").write_text_content(BytesText::new(cell_name))?;
-                let position = format!("{} {}", self.x, self.y);
+                writer.create_element("name").write_text_content(BytesText::new(&self.name))?;
+                let position = format!("{} {}", self.position.x, self.position.y);
                 writer.create_element("pos").write_text_content(BytesText::new(&position))?;
-                let size = format!("{} {}", self.width, self.height);
+                let size = format!("{} {}", self.size.x, self.size.y);
                 writer.create_element("size").write_text_content(BytesText::new(&size))?;
-                let pivot = format!("{} {}", self.pivot_x, self.pivot_y);
+                let pivot = format!("{} {}", self.pivot.x, self.pivot.y);
                 writer.create_element("pivot").write_text_content(BytesText::new(&pivot))?;
-                writer.create_element("rotated").write_text_content(BytesText::new("0"))?;
+                writer.create_element("rotated").write_text_content(BytesText::new(&format!("{}", self.rotated as u8)))?;
                 writer.create_element("orgImageName").write_text_content(BytesText::new(""))?;
                 writer.create_element("posStable").write_text_content(BytesText::new("0"))?;
-                writer.create_element("ismesh").write_text_content(BytesText::new("0"))?;
+                let is_mesh = self.mesh.is_some() as u8;
+                writer.create_element("ismesh").write_text_content(BytesText::new(&format!("{}", is_mesh)))?;
                 writer.create_element("divtype").write_text_content(BytesText::new("unknown"))?;
                 writer.create_element("innerPoint").write_empty()?;
                 writer.create_element("outerPoint").write_empty()?;
-                writer.create_element("meshPointList").write_empty()?;
-                writer.create_element("meshTriList").write_empty()?;
+                match &self.mesh {
+                    None => {
+                        writer.create_element("meshPointList").write_empty()?;
+                        writer.create_element("meshTriList").write_empty()?;
+                    },
+                    Some(mesh) => {
+                        writer.create_element("meshPointList").write_inner_content(|writer| {
+                            for point in &mesh.points {
+                                writer.create_element("value")
+                                    .write_text_content(BytesText::new(&format!("{} {}", point.x, point.y)))?;
+                            }
+                            Ok(())
+                        })?;
+                        writer.create_element("meshTriList").write_inner_content(|writer| {
+                            for tri in &mesh.triangles {
+                                writer.create_element("value")
+                                    .write_text_content(BytesText::new(&format!("{} {} {}", tri.x, tri.y, tri.z)))?;
+                            }
+                            Ok(())
+                        })?;
+                    }
+                }
                 Ok(())
             })?;
         Ok(())
@@ -179,6 +303,14 @@ const _: () = {
     ["Size of CellMap"][size_of::<CellMap>() - 0x10];
 };
 
+unsafe impl BinaryStruct for CellMap {
+    const NAME: &'static str = "CellMap";
+    fn validate(binary: &[u8], offset: usize) -> Result<(), ParseError> {
+        check_enum("TexWrapMode", binary, offset + std::mem::offset_of!(CellMap, wrap_mode), TexWrapMode::mirror as u16)?;
+        check_enum("TexFilterMode", binary, offset + std::mem::offset_of!(CellMap, filter_mode), TexFilterMode::linear as u16)
+    }
+}
+
 impl PartialEq for CellMap {
     fn eq(&self, other: &Self) -> bool {
         self.index == other.index
@@ -195,7 +327,7 @@ impl Hash for CellMap {
 
 impl PartialOrd for CellMap {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.index.partial_cmp(&other.index)
+        Some(self.cmp(other))
     }
 }
 
@@ -206,8 +338,11 @@ impl Ord for CellMap {
 }
 
 impl CellMap {
-    pub fn get_name(&self, binary: &[u8]) -> &str {
-        self.name.value(binary)
+    pub fn get_name<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.name.value(binary, "CellMap.name")
+    }
+    pub fn get_image_path<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.image_path.value(binary, "CellMap.image_path")
     }
     pub fn get_index(&self) -> u16 {
         self.index
@@ -218,59 +353,20 @@ impl CellMap {
     pub fn get_filter_mode(&self) -> TexFilterMode {
         self.filter_mode
     }
-}
-
-#[repr(C)]
-#[derive(Debug)]
-pub struct Cell<'a> {
-    data: &'a CellMap,
-    list: Vec<&'a CellEntry>
-}
-
-impl<'a> PartialEq for Cell<'a> {
-    fn eq(&self, other: &Self) -> bool {
-        self.data.eq(other.data)
-    }
-}
-
-impl<'a> Eq for Cell<'a> {}
-
-impl<'a> Hash for Cell<'a> {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.data.hash(state)
-    }
-}
 
-impl<'a> PartialOrd for Cell<'a>{
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.data.partial_cmp(&other.data)
+    pub fn to_model(&self, binary: &[u8], cells: Vec<model::Cell>) -> Result<model::CellMap, ParseError> {
+        Ok(model::CellMap {
+            name: self.get_name(binary)?.to_string(),
+            image_path: self.get_image_path(binary)?.to_string(),
+            index: self.index,
+            wrap_mode: self.wrap_mode,
+            filter_mode: self.filter_mode,
+            cells,
+        })
     }
 }
 
-impl<'a> Ord for Cell<'a> {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.data.cmp(&other.data)
-    }
-}
-
-impl<'a> Cell<'a> {
-    pub fn new(data: &'a CellMap, first: &'a CellEntry) -> Self {
-        Self { data, list: vec![first] }
-    }
-
-    pub fn add(&mut self, new: &'a CellEntry) {
-        self.list.push(new);
-    }
-
-    pub fn get_name(&self, binary: &[u8]) -> &str {
-        self.data.name.value(binary)
-    }
-
-    pub fn get_name_by_index(&self, index: u16, binary: &[u8]) -> Option<&str> {
-        self.list.iter().find(|c| c.index == index)
-            .map(|c| c.get_name(binary))
-    }
-
+impl model::CellMap {
     // .ssce XML format:
     //
     // <?xml version="1.0" encoding="utf-8" standalone="yes"?>
@@ -308,43 +404,43 @@ impl<'a> Cell<'a> {
     //      {end foreach}
     //  </cells>
     // </SpriteStudioCellMap>
-    pub fn to_xml<F>(&self, binary: &[u8], get_img_params: F)
+    pub fn to_xml<F>(&self, get_img_params: F)
         -> Result<Vec<u8>, Box<dyn Error>>
     where F: Fn(&str) -> std::io::Result<(String, UVec2)>,
     {
         let xml_fmt = "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n";
         let mut cursor = Cursor::new(xml_fmt.as_bytes().to_vec());
         cursor.seek(SeekFrom::End(0))?;
-        let mut writer = Writer::new_with_indent(cursor, '\t' as u8, 1);
+        let mut writer = Writer::new_with_indent(cursor, b'\t', 1);
         writer.create_element("SpriteStudioCellMap")
             .with_attributes([("version", "2.00.00")])
-            .write_inner_content(|writer| self.to_xml_body(writer, binary, get_img_params))?;
+            .write_inner_content(|writer| self.to_xml_body(writer, get_img_params))?;
         Ok(writer.into_inner().into_inner())
     }
-    pub(crate) fn to_xml_body<F, W: Write>(&self, writer: &mut Writer<W>, binary: &[u8], get_img_params: F) -> std::io::Result<()>
+    pub(crate) fn to_xml_body<F, W: Write>(&self, writer: &mut Writer<W>, get_img_params: F) -> std::io::Result<()>
     where F: Fn(&str) -> std::io::Result<(String, UVec2)> {
         writer.create_element("name")
-            .write_text_content(BytesText::new(self.data.name.value(binary)))?;
+            .write_text_content(BytesText::new(&self.name))?;
         writer.create_element("exportPath").write_text_content(BytesText::new(""))?;
         writer.create_element("generator").write_text_content(BytesText::new("SpriteStudio"))?;
         writer.create_element("packed").write_text_content(BytesText::new("0"))?;
-        let (img_path, dims) = get_img_params(self.data.image_path.value(binary))?;
+        let (img_path, dims) = get_img_params(&self.image_path)?;
         writer.create_element("imagePath")
             .write_text_content(BytesText::new(&img_path))?;
         writer.create_element("pixelSize")
             .write_text_content(BytesText::new(&format!("{} {}", dims.x, dims.y)))?;
         writer.create_element("overrideTexSettings").write_text_content(BytesText::new("0"))?;
         writer.create_element("wrapMode")
-            .write_text_content(BytesText::new(&format!("{:?}", self.data.wrap_mode)))?;
+            .write_text_content(BytesText::new(&format!("{:?}", self.wrap_mode)))?;
         writer.create_element("filterMode")
-            .write_text_content(BytesText::new(&format!("{:?}", self.data.filter_mode)))?;
+            .write_text_content(BytesText::new(&format!("{:?}", self.filter_mode)))?;
         writer.create_element("imagePathAtImport").write_text_content(BytesText::new(""))?;
         writer.create_element("packInfoFilePath").write_text_content(BytesText::new(""))?;
         tex_pack_settings_to_xml(writer)?;
         writer.create_element("cells")
             .write_inner_content(|writer| {
-                for cell in self.list.iter() {
-                    cell.to_xml(writer, binary)?;
+                for cell in self.cells.iter() {
+                    cell.to_xml(writer)?;
                 }
                 Ok(())
             })?;
@@ -363,4 +459,76 @@ pub(crate) fn tex_pack_settings_to_xml<W: Write>(writer: &mut Writer<W>) -> std:
             Ok(())
         })?;
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+    use crate::test_data::{AlignedBinary, SsbpWriter};
+    use crate::util::Ptr;
+    use super::{infer_texture_size, CellEntry};
+
+    /// Write a cell at `position` in a 64x64 texture, whose region in the texture is `region`
+    /// pixels big
+    fn cell(position: [u16; 2], size: [u16; 2], pivot: Vec2, region: [u16; 2]) -> AlignedBinary {
+        let mut writer = SsbpWriter::default();
+        writer.u32(0);
+        writer.u32(0);
+        for value in [0, position[0], position[1], size[0], size[1]] {
+            writer.u16(value);
+        }
+        writer.f32(pivot.x);
+        writer.f32(pivot.y);
+        writer.f32(position[0] as f32 / 64.);
+        writer.f32(position[1] as f32 / 64.);
+        writer.f32((position[0] + region[0]) as f32 / 64.);
+        writer.f32((position[1] + region[1]) as f32 / 64.);
+        writer.finish()
+    }
+
+    fn entry(binary: &AlignedBinary) -> &CellEntry {
+        Ptr::<CellEntry>::new(0).value(binary.bytes()).unwrap()
+    }
+
+    #[test]
+    fn unrotated_cell() {
+        let binary = cell([16, 0], [16, 32], Vec2::ZERO, [16, 32]);
+        let cell = entry(&binary);
+        assert!(!cell.is_rotated_in(Vec2::splat(64.)));
+        assert_eq!(cell.uv_to_point(Vec2::new(0.25, 0.), false), Vec2::ZERO);
+        assert_eq!(cell.uv_to_point(Vec2::new(0.5, 0.5), false), Vec2::new(16., 32.));
+        assert_eq!(cell.uv_to_point(Vec2::new(0.375, 0.125), false), Vec2::new(8., 8.));
+    }
+
+    #[test]
+    fn rotated_cell() {
+        let binary = cell([16, 0], [16, 32], Vec2::ZERO, [32, 16]);
+        let cell = entry(&binary);
+        assert!(cell.is_rotated_in(Vec2::splat(64.)));
+        // the cell's top left is in the top right of the region, and its top right in the
+        // bottom right
+        assert_eq!(cell.uv_to_point(Vec2::new(0.75, 0.), true), Vec2::ZERO);
+        assert_eq!(cell.uv_to_point(Vec2::new(0.75, 0.25), true), Vec2::new(16., 0.));
+        assert_eq!(cell.uv_to_point(Vec2::new(0.25, 0.), true), Vec2::new(0., 32.));
+    }
+
+    #[test]
+    fn rotated_pivot_stays_on_the_same_texel() {
+        let pivot = Vec2::new(0.25, 0.1);
+        let binary = cell([16, 0], [16, 32], pivot, [32, 16]);
+        let cell = entry(&binary);
+        // the pivot's texel in the region, which is 32x16
+        let texel = Vec2::new(16. + (0.5 + pivot.x) * 32., (0.5 + pivot.y) * 16.);
+        let local = cell.point_to_local(cell.uv_to_point(texel / 64., true), true);
+        assert!(local.abs_diff_eq(Vec2::ZERO, 1e-4), "{}", local);
+        assert_eq!(cell.get_pivot(true), Vec2::new(0.1, -0.25));
+    }
+
+    #[test]
+    fn texture_size_from_cells_on_the_top_edge() {
+        let a = cell([0, 0], [16, 16], Vec2::ZERO, [16, 16]);
+        let b = cell([16, 0], [16, 32], Vec2::ZERO, [32, 16]);
+        assert_eq!(infer_texture_size(&[entry(&a), entry(&b)]), Some(Vec2::splat(64.)));
+        assert_eq!(infer_texture_size(&[entry(&a)]), None);
+    }
+}
@@ -0,0 +1,334 @@
+// SSBP files only store the value of each attribute on every frame, so eased tweens made in
+// SpriteStudio are read back as a run of linear keyframes. Curve fitting walks a track from its
+// first keyframe and extends each segment for as long as one of SpriteStudio's curves reproduces
+// every baked frame within the attribute's tolerance, preferring the simplest curve that fits.
+// Attributes are measured in different units, so they use the same tolerances as keyframe
+// reduction.
+//
+// Curves are evaluated the same way as the SpriteStudio 6 runtime (ssInterpolation.cpp), including
+// the fixed number of bisection steps used to find the bezier parameter for a frame.
+
+use crate::cell::InterpolateType;
+use crate::model::{self, KeyframeCurve};
+use crate::reduce::KeyframeTolerances;
+
+/// Bezier time handles to try, as a fraction of the segment length
+const BEZIER_HANDLES: [f32; 5] = [0.1, 0.25, 0.5, 0.75, 0.9];
+
+/// Part of a bezier or eased curve usually can't be matched by another curve of the same type, so
+/// keep trying longer segments for this many frames after a segment stops fitting
+const LOOKAHEAD_FRAMES: usize = 16;
+
+fn cubic(u: f32, p0: f32, p1: f32, p2: f32, p3: f32) -> f32 {
+    let v = 1. - u;
+    v * v * v * p0 + 3. * v * v * u * p1 + 3. * v * u * u * p2 + u * u * u * p3
+}
+
+/// Find the bezier parameter for a point in time between two keyframes
+fn bezier_param(curve: &KeyframeCurve, start_frame: f32, end_frame: f32, time: f32) -> f32 {
+    let target = (end_frame - start_frame) * time + start_frame;
+    let (mut u, mut range) = (0.5f32, 0.5f32);
+    for _ in 0..8 {
+        let x = cubic(u, start_frame, start_frame + curve.start_time,
+            end_frame + curve.end_time, end_frame);
+        range /= 2.;
+        match x > target {
+            true => u -= range,
+            false => u += range
+        }
+    }
+    u
+}
+
+/// Get the value at `time` (0 to 1) between a keyframe at `start` and the next keyframe at `end`,
+/// where each is a (frame, value) pair
+pub fn interpolate(ip_type: InterpolateType, curve: Option<&KeyframeCurve>,
+    start: (f32, f32), end: (f32, f32), time: f32) -> f32 {
+    let ((start_frame, start_value), (end_frame, end_value)) = (start, end);
+    match (ip_type, curve) {
+        (InterpolateType::none, _) => start_value,
+        (InterpolateType::acceleration, _) => start_value + (end_value - start_value) * time * time,
+        (InterpolateType::deceleration, _) => {
+            let rev = 1. - time;
+            start_value + (end_value - start_value) * (1. - rev * rev)
+        },
+        (InterpolateType::hermite, Some(c)) => {
+            let (t2, t3) = (time * time, time * time * time);
+            (2. * t3 - 3. * t2 + 1.) * start_value + (-2. * t3 + 3. * t2) * end_value
+                + (t3 - 2. * t2 + time) * (c.start_value - start_value) + (t3 - t2) * (c.end_value - end_value)
+        },
+        (InterpolateType::bezier, Some(c)) => {
+            let u = bezier_param(c, start_frame, end_frame, time);
+            cubic(u, start_value, start_value + c.start_value, end_value + c.end_value, end_value)
+        },
+        _ => start_value + (end_value - start_value) * time
+    }
+}
+
+/// Solve the least squares fit of `residual = a * b0 + b * b1`. A small amount of damping picks
+/// the smallest handles when there are too few samples to decide on a single answer.
+fn least_squares(samples: impl Iterator<Item = (f32, f32, f32)>) -> Option<(f32, f32)> {
+    let (mut s00, mut s01, mut s11, mut r0, mut r1) = (0f64, 0f64, 0f64, 0f64, 0f64);
+    for (b0, b1, r) in samples {
+        let (b0, b1, r) = (b0 as f64, b1 as f64, r as f64);
+        s00 += b0 * b0;
+        s01 += b0 * b1;
+        s11 += b1 * b1;
+        r0 += b0 * r;
+        r1 += b1 * r;
+    }
+    let damping = (s00 + s11) * 1e-7;
+    let (s00, s11) = (s00 + damping, s11 + damping);
+    let det = s00 * s11 - s01 * s01;
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    Some((((r0 * s11 - r1 * s01) / det) as f32, ((s00 * r1 - s01 * r0) / det) as f32))
+}
+
+/// Frames between two keyframes, which are the only ones that use the curve. The runtime shows the
+/// keyframe's own value on the frame it's placed on.
+fn interior(samples: &[f32]) -> impl Iterator<Item = (f32, f32)> + '_ {
+    let len = (samples.len() - 1) as f32;
+    samples[1..samples.len() - 1].iter().enumerate().map(move |(i, v)| ((i + 1) as f32 / len, *v))
+}
+
+fn fits(samples: &[f32], ip_type: InterpolateType, curve: Option<&KeyframeCurve>, tolerance: f32) -> bool {
+    let len = (samples.len() - 1) as f32;
+    let (start, end) = ((0., samples[0]), (len, samples[samples.len() - 1]));
+    interior(samples).all(|(t, v)| (interpolate(ip_type, curve, start, end, t) - v).abs() <= tolerance)
+}
+
+fn fit_hermite(samples: &[f32]) -> Option<KeyframeCurve> {
+    let (start, end) = (samples[0], samples[samples.len() - 1]);
+    let (m0, m1) = least_squares(interior(samples).map(|(t, v)| {
+        let (t2, t3) = (t * t, t * t * t);
+        let base = (2. * t3 - 3. * t2 + 1.) * start + (-2. * t3 + 3. * t2) * end;
+        (t3 - 2. * t2 + t, t3 - t2, v - base)
+    }))?;
+    Some(KeyframeCurve { start_time: 0., start_value: start + m0, end_time: 0., end_value: end + m1 })
+}
+
+fn fit_bezier(samples: &[f32], start_handle: f32, end_handle: f32) -> Option<KeyframeCurve> {
+    let len = (samples.len() - 1) as f32;
+    let (start, end) = (samples[0], samples[samples.len() - 1]);
+    let handles = KeyframeCurve {
+        start_time: start_handle * len, start_value: 0.,
+        end_time: -end_handle * len, end_value: 0.
+    };
+    let (a, b) = least_squares(interior(samples).map(|(t, v)| {
+        let u = bezier_param(&handles, 0., len, t);
+        let w = 1. - u;
+        (3. * w * w * u, 3. * w * u * u, v - cubic(u, start, start, end, end))
+    }))?;
+    Some(KeyframeCurve { start_value: a, end_value: b, ..handles })
+}
+
+/// Find the simplest curve which passes through every sample, which are one frame apart
+pub fn fit_segment(samples: &[f32], tolerance: f32) -> Option<(InterpolateType, Option<KeyframeCurve>)> {
+    if samples.len() < 2 {
+        return None;
+    }
+    for ip_type in [InterpolateType::linear, InterpolateType::acceleration, InterpolateType::deceleration] {
+        if fits(samples, ip_type, None, tolerance) {
+            return Some((ip_type, None));
+        }
+    }
+    if samples.len() < 3 {
+        return None;
+    }
+    if let Some(c) = fit_hermite(samples).filter(|c| fits(samples, InterpolateType::hermite, Some(c), tolerance)) {
+        return Some((InterpolateType::hermite, Some(c)));
+    }
+    for start_handle in BEZIER_HANDLES {
+        for end_handle in BEZIER_HANDLES {
+            if let Some(c) = fit_bezier(samples, start_handle, end_handle)
+                .filter(|c| fits(samples, InterpolateType::bezier, Some(c), tolerance)) {
+                return Some((InterpolateType::bezier, Some(c)));
+            }
+        }
+    }
+    None
+}
+
+/// Split a track sampled on every frame into curve segments. Returns the sample index each
+/// segment starts on, followed by the index of the last sample.
+pub fn fit_track(samples: &[f32], tolerance: f32) -> Vec<(usize, InterpolateType, Option<KeyframeCurve>)> {
+    let mut segments = vec![];
+    let mut start = 0;
+    while start + 1 < samples.len() {
+        let mut best = (start + 1, InterpolateType::linear, None);
+        for end in start + 2..samples.len() {
+            if end > best.0 + LOOKAHEAD_FRAMES {
+                break;
+            }
+            if let Some((ip_type, curve)) = fit_segment(&samples[start..=end], tolerance) {
+                best = (end, ip_type, curve);
+            }
+        }
+        segments.push((start, best.1, best.2));
+        start = best.0;
+    }
+    segments.push((start, InterpolateType::linear, None));
+    segments
+}
+
+impl model::Attribute {
+    /// Replace a track of linear keyframes with fitted curves. Tracks that don't hold numbers or
+    /// already use other curves are left as they are.
+    pub fn fit_curves(&mut self, tolerance: f32) {
+        if self.keyframes.len() < 3 || !self.keyframes.iter().all(|k|
+            k.interpolation == InterpolateType::linear && k.value.get_scalar().is_some()) {
+            return;
+        }
+        // keys are linear, so the baked value on each frame can be recovered from them
+        let first = self.keyframes[0].frame;
+        let mut samples = Vec::with_capacity(self.keyframes[self.keyframes.len() - 1].frame - first + 1);
+        for pair in self.keyframes.windows(2) {
+            let (start, end) = (pair[0].value.get_scalar().unwrap(), pair[1].value.get_scalar().unwrap());
+            let len = (pair[1].frame - pair[0].frame) as f32;
+            for i in 0..pair[1].frame - pair[0].frame {
+                samples.push(start + (end - start) * (i as f32 / len));
+            }
+        }
+        samples.push(self.keyframes[self.keyframes.len() - 1].value.get_scalar().unwrap());
+        let template = self.keyframes[0].value.clone();
+        self.keyframes = fit_track(&samples, tolerance).into_iter()
+            .map(|(i, interpolation, curve)| model::Keyframe {
+                frame: first + i,
+                interpolation,
+                curve,
+                value: template.with_scalar(samples[i]).unwrap()
+            })
+            .collect();
+    }
+}
+
+impl model::Animation {
+    pub fn fit_curves(&mut self, tolerances: &KeyframeTolerances) {
+        // setup data is written without interpolation
+        if self.is_setup() {
+            return;
+        }
+        for part_anime in &mut self.part_animes {
+            for attribute in &mut part_anime.attributes {
+                let tolerance = tolerances.get(&attribute.tag);
+                attribute.fit_curves(tolerance);
+            }
+        }
+    }
+}
+
+impl model::Project {
+    /// Fit curves to the tracks in every animation. See [`model::Attribute::fit_curves`]
+    pub fn fit_curves(&mut self, tolerances: &KeyframeTolerances) {
+        for anime_pack in &mut self.anime_packs {
+            for animation in &mut anime_pack.animations {
+                animation.fit_curves(tolerances);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{UVec2, Vec2};
+    use crate::anime::AttributeKeyframe;
+    use crate::cell::InterpolateType;
+    use crate::model::{Animation, Attribute, Keyframe, KeyframeCurve, PartAnime};
+    use super::*;
+
+    /// Tolerance for the position-sized values these tests use
+    fn tolerance() -> f32 {
+        KeyframeTolerances::default().get("POSX")
+    }
+
+    /// Bake a segment the way the runtime shows it on each frame
+    fn bake(ip_type: InterpolateType, curve: Option<&KeyframeCurve>, start: f32, end: f32, frames: usize) -> Vec<f32> {
+        let len = frames as f32;
+        (0..=frames).map(|f| match f {
+            0 => start,
+            f if f == frames => end,
+            f => interpolate(ip_type, curve, (0., start), (len, end), f as f32 / len)
+        }).collect()
+    }
+
+    /// Check that a fitted segment reproduces every baked frame between its keyframes
+    fn assert_fits(samples: &[f32], ip_type: InterpolateType, curve: Option<&KeyframeCurve>) {
+        let len = (samples.len() - 1) as f32;
+        let (start, end) = ((0., samples[0]), (len, samples[samples.len() - 1]));
+        for (f, v) in samples.iter().enumerate().take(samples.len() - 1).skip(1) {
+            let fitted = interpolate(ip_type, curve, start, end, f as f32 / len);
+            assert!((fitted - v).abs() <= tolerance(), "frame {}: {} != {}", f, fitted, v);
+        }
+    }
+
+    #[test]
+    fn linear_ramp_is_a_single_key() {
+        let samples = bake(InterpolateType::linear, None, 10., 40., 30);
+        let segments = fit_track(&samples, tolerance());
+        assert_eq!(segments.len(), 2);
+        assert_eq!((segments[0].0, segments[0].1), (0, InterpolateType::linear));
+        assert_eq!(segments[1].0, 30);
+    }
+
+    #[test]
+    fn eased_segments_fit_back() {
+        for ip_type in [InterpolateType::acceleration, InterpolateType::deceleration] {
+            let samples = bake(ip_type, None, 1., -2., 20);
+            let (fitted, curve) = fit_segment(&samples, tolerance()).unwrap();
+            assert_eq!(fitted, ip_type);
+            assert_fits(&samples, fitted, curve.as_ref());
+            assert_eq!(fit_track(&samples, tolerance()).len(), 2);
+        }
+    }
+
+    #[test]
+    fn hermite_segment_fits_back() {
+        let curve = KeyframeCurve { start_time: 0., start_value: 60., end_time: 0., end_value: -20. };
+        let samples = bake(InterpolateType::hermite, Some(&curve), 0., 100., 24);
+        let (fitted, curve) = fit_segment(&samples, tolerance()).unwrap();
+        assert_eq!(fitted, InterpolateType::hermite);
+        assert_fits(&samples, fitted, curve.as_ref());
+    }
+
+    #[test]
+    fn bezier_segment_fits_back() {
+        let curve = KeyframeCurve { start_time: 4., start_value: 30., end_time: -12., end_value: 5. };
+        let samples = bake(InterpolateType::bezier, Some(&curve), 0., 50., 16);
+        let (fitted, curve) = fit_segment(&samples, tolerance()).unwrap();
+        assert!(matches!(fitted, InterpolateType::hermite | InterpolateType::bezier));
+        assert_fits(&samples, fitted, curve.as_ref());
+    }
+
+    #[test]
+    fn each_attribute_uses_its_own_tolerance() {
+        // a ramp that's 0.005 off a straight line on one frame
+        let samples: Vec<f32> = (0..=10).map(|f| f as f32 * 0.1 + if f == 5 { 0.005 } else { 0. }).collect();
+        let track = |tag: &str, value: fn(f32) -> AttributeKeyframe| Attribute {
+            tag: tag.to_string(),
+            keyframes: samples.iter().enumerate().map(|(frame, v)| Keyframe {
+                frame, interpolation: InterpolateType::linear, curve: None, value: value(*v)
+            }).collect()
+        };
+        let mut animation = Animation {
+            name: "anim".to_string(),
+            start_frame: 0,
+            end_frame: 10,
+            total_frames: 11,
+            fps: 30,
+            canvas_size: UVec2::new(320, 320),
+            canvas_pivot: Vec2::ZERO,
+            labels: vec![],
+            part_animes: vec![PartAnime {
+                part_name: "part".to_string(),
+                attributes: vec![track("POSX", AttributeKeyframe::PositionX), track("SCLX", AttributeKeyframe::ScaleX)]
+            }]
+        };
+        animation.fit_curves(&KeyframeTolerances::default());
+        let [position, scale] = &animation.part_animes[0].attributes[..] else { panic!() };
+        // well within a hundredth of a pixel, but not within a thousandth of the scale
+        assert_eq!(position.keyframes.len(), 2);
+        assert!(scale.keyframes.len() > 2);
+    }
+}
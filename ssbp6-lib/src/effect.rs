@@ -4,14 +4,22 @@
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 use std::io::{Cursor, Seek, SeekFrom, Write};
+use glam::Vec2;
 use quick_xml::events::BytesText;
 use quick_xml::Writer;
-use crate::cell::CellEntry;
-use crate::util::{create_blank_element, Ptr, StringPtr};
+use crate::cell::{CastError, CellEntry};
+use crate::model;
+use crate::particle::{color_range, EmitterSettings};
+use crate::util::{check_bounds, check_enum, create_blank_element, BinaryStruct, ParseError, Ptr, StringPtr};
 
 #[derive(Debug)]
 pub enum EffectError {
-    GotBaseEffect
+    CellIndexOutOfRange(i16),
+    MissingRootNode,
+    /// (node, parent) where the parent doesn't come before the node
+    InvalidNodeParent(usize, i16),
+    /// (node, parent) where the node can't be a child of its parent's type
+    InvalidNodeNesting(usize, EffectNodeType, EffectNodeType),
 }
 
 impl Error for EffectError {}
@@ -24,6 +32,7 @@ impl Display for EffectError {
 
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EffectNodeType {
     Root,
     Emmiter,
@@ -32,6 +41,7 @@ pub enum EffectNodeType {
 
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BehaviorType {
     Base,
     Basic	,
@@ -39,7 +49,8 @@ pub enum BehaviorType {
     Delay,
     Gravity,
     Position,
-    //TransPosition,
+    // TransPosition is commented out of SsEffectFunctionType in the SpriteStudio 6 SDK, so it has
+    // no ID and the converter never writes it. The IDs after it are numbered without it.
     Rotation,
     TransRotation,
     TransSpeed,
@@ -54,47 +65,202 @@ pub enum BehaviorType {
     InfiniteEmitEnabled,
 }
 
+impl TryFrom<u16> for BehaviorType {
+    type Error = CastError;
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        if value <= Self::InfiniteEmitEnabled as u16 {
+            Ok(unsafe { std::mem::transmute::<u16, Self>(value) })
+        } else {
+            Err(CastError::new("BehaviorType", value as usize))
+        }
+    }
+}
+
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RenderBlendType {
     Mix,
     Add,
 }
 
+/// Size of the largest behavior (Basic), which is how much of an unknown behavior is kept
+const MAX_BEHAVIOR_SIZE: usize = size_of::<Basic>();
+
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Behavior {
-    _type: BehaviorType
+    _type: u16
+}
+
+unsafe impl BinaryStruct for Behavior {
+    const NAME: &'static str = "Behavior";
+    // Behavior is the header of a larger structure, so the concrete type it points to needs to
+    // fit as well. Unknown types are only checked when they're decoded.
+    fn validate(binary: &[u8], offset: usize) -> Result<(), ParseError> {
+        let value = u16::from_ne_bytes([binary[offset], binary[offset + 1]]);
+        match BehaviorType::try_from(value) {
+            Ok(BehaviorType::Base) | Err(_) => Ok(()),
+            Ok(BehaviorType::Basic) => check_bounds::<Basic>(binary, offset, 1),
+            Ok(BehaviorType::RndSeedChange) => check_bounds::<RndSeedChange>(binary, offset, 1),
+            Ok(BehaviorType::Delay) => check_bounds::<Delay>(binary, offset, 1),
+            Ok(BehaviorType::Gravity) => check_bounds::<Gravity>(binary, offset, 1),
+            Ok(BehaviorType::Position) => check_bounds::<Position>(binary, offset, 1),
+            Ok(BehaviorType::Rotation) => check_bounds::<Rotation>(binary, offset, 1),
+            Ok(BehaviorType::TransRotation) => check_bounds::<TransRotation>(binary, offset, 1),
+            Ok(BehaviorType::TransSpeed) => check_bounds::<TransSpeed>(binary, offset, 1),
+            Ok(BehaviorType::TangentialAcceleration) => check_bounds::<TangentialAcceleration>(binary, offset, 1),
+            Ok(BehaviorType::InitColor) => check_bounds::<InitColor>(binary, offset, 1),
+            Ok(BehaviorType::TransColor) => check_bounds::<TransColor>(binary, offset, 1),
+            Ok(BehaviorType::AlphaFade) => check_bounds::<AlphaFade>(binary, offset, 1),
+            Ok(BehaviorType::Size) => check_bounds::<Size>(binary, offset, 1),
+            Ok(BehaviorType::TransSize) => check_bounds::<TransSize>(binary, offset, 1),
+            Ok(BehaviorType::PointGravity) => check_bounds::<PointGravity>(binary, offset, 1),
+            Ok(BehaviorType::TurnToDirectionEnabled) => check_bounds::<TurnToDirectionEnabled>(binary, offset, 1),
+            Ok(BehaviorType::InfiniteEmitEnabled) => check_bounds::<InfiniteEmitEnabled>(binary, offset, 1),
+        }
+    }
 }
 
 impl Behavior {
-    pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>, binary: &[u8]) -> std::io::Result<()> {
-        match self._type {
-            BehaviorType::Base => return Err(std::io::Error::other(Box::new(EffectError::GotBaseEffect))),
-            BehaviorType::Basic => unsafe { std::mem::transmute::<_, &Basic>(self) }.to_xml(writer),
-            BehaviorType::RndSeedChange => unsafe { std::mem::transmute::<_, &RndSeedChange>(self) }.to_xml(writer),
-            BehaviorType::Delay => unsafe { std::mem::transmute::<_, &Delay>(self) }.to_xml(writer),
-            BehaviorType::Gravity => unsafe { std::mem::transmute::<_, &Gravity>(self) }.to_xml(writer),
-            BehaviorType::Position => unsafe { std::mem::transmute::<_, &Position>(self) }.to_xml(writer),
-            BehaviorType::Rotation => unsafe { std::mem::transmute::<_, &Rotation>(self) }.to_xml(writer),
-            BehaviorType::TransRotation => unsafe { std::mem::transmute::<_, &TransRotation>(self) }.to_xml(writer),
-            BehaviorType::TransSpeed => unsafe { std::mem::transmute::<_, &TransSpeed>(self) }.to_xml(writer),
-            BehaviorType::TangentialAcceleration => unsafe { std::mem::transmute::<_, &TangentialAcceleration>(self) }.to_xml(writer),
-            BehaviorType::InitColor => unsafe { std::mem::transmute::<_, &InitColor>(self) }.to_xml(writer),
-            BehaviorType::TransColor => unsafe { std::mem::transmute::<_, &TransColor>(self) }.to_xml(writer),
-            BehaviorType::AlphaFade => unsafe { std::mem::transmute::<_, &AlphaFade>(self) }.to_xml(writer),
-            BehaviorType::Size => unsafe { std::mem::transmute::<_, &Size>(self) }.to_xml(writer),
-            BehaviorType::TransSize => unsafe { std::mem::transmute::<_, &TransSize>(self) }.to_xml(writer),
-            BehaviorType::PointGravity => unsafe { std::mem::transmute::<_, &PointGravity>(self) }.to_xml(writer),
-            BehaviorType::TurnToDirectionEnabled => unsafe { std::mem::transmute::<_, &TurnToDirectionEnabled>(self) }.to_xml(writer),
-            BehaviorType::InfiniteEmitEnabled => unsafe { std::mem::transmute::<_, &InfiniteEmitEnabled>(self) }.to_xml(writer),
-        }?;
-        Ok(())
+    /// None if the ID isn't a known behavior
+    pub fn get_type(&self) -> Option<BehaviorType> {
+        BehaviorType::try_from(self._type).ok()
+    }
+    pub fn get_type_id(&self) -> u16 {
+        self._type
+    }
+}
+
+impl Ptr<Behavior> {
+    /// Copy out the concrete behavior this points to
+    pub fn to_kind(&self, binary: &[u8]) -> Result<BehaviorKind, ParseError> {
+        let behavior = self.value(binary)?;
+        Ok(match behavior.get_type() {
+            // Base is only the header shared by the other behaviors, so it's never exported
+            None | Some(BehaviorType::Base) => {
+                let start = self.get_offset();
+                let end = (start + MAX_BEHAVIOR_SIZE).min(binary.len());
+                BehaviorKind::Unknown { id: behavior._type, data: binary[start..end].to_vec() }
+            },
+            Some(BehaviorType::Basic) => BehaviorKind::Basic(self.cast::<Basic>().value(binary)?.clone()),
+            Some(BehaviorType::RndSeedChange) => BehaviorKind::RndSeedChange(self.cast::<RndSeedChange>().value(binary)?.clone()),
+            Some(BehaviorType::Delay) => BehaviorKind::Delay(self.cast::<Delay>().value(binary)?.clone()),
+            Some(BehaviorType::Gravity) => BehaviorKind::Gravity(self.cast::<Gravity>().value(binary)?.clone()),
+            Some(BehaviorType::Position) => BehaviorKind::Position(self.cast::<Position>().value(binary)?.clone()),
+            Some(BehaviorType::Rotation) => BehaviorKind::Rotation(self.cast::<Rotation>().value(binary)?.clone()),
+            Some(BehaviorType::TransRotation) => BehaviorKind::TransRotation(self.cast::<TransRotation>().value(binary)?.clone()),
+            Some(BehaviorType::TransSpeed) => BehaviorKind::TransSpeed(self.cast::<TransSpeed>().value(binary)?.clone()),
+            Some(BehaviorType::TangentialAcceleration) => BehaviorKind::TangentialAcceleration(self.cast::<TangentialAcceleration>().value(binary)?.clone()),
+            Some(BehaviorType::InitColor) => BehaviorKind::InitColor(self.cast::<InitColor>().value(binary)?.clone()),
+            Some(BehaviorType::TransColor) => BehaviorKind::TransColor(self.cast::<TransColor>().value(binary)?.clone()),
+            Some(BehaviorType::AlphaFade) => BehaviorKind::AlphaFade(self.cast::<AlphaFade>().value(binary)?.clone()),
+            Some(BehaviorType::Size) => BehaviorKind::Size(self.cast::<Size>().value(binary)?.clone()),
+            Some(BehaviorType::TransSize) => BehaviorKind::TransSize(self.cast::<TransSize>().value(binary)?.clone()),
+            Some(BehaviorType::PointGravity) => BehaviorKind::PointGravity(self.cast::<PointGravity>().value(binary)?.clone()),
+            Some(BehaviorType::TurnToDirectionEnabled) => BehaviorKind::TurnToDirectionEnabled(self.cast::<TurnToDirectionEnabled>().value(binary)?.clone()),
+            Some(BehaviorType::InfiniteEmitEnabled) => BehaviorKind::InfiniteEmitEnabled(self.cast::<InfiniteEmitEnabled>().value(binary)?.clone()),
+        })
+    }
+}
+
+/// Owned copy of a concrete behavior
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BehaviorKind {
+    Basic(Basic),
+    RndSeedChange(RndSeedChange),
+    Delay(Delay),
+    Gravity(Gravity),
+    Position(Position),
+    Rotation(Rotation),
+    TransRotation(TransRotation),
+    TransSpeed(TransSpeed),
+    TangentialAcceleration(TangentialAcceleration),
+    InitColor(InitColor),
+    TransColor(TransColor),
+    AlphaFade(AlphaFade),
+    Size(Size),
+    TransSize(TransSize),
+    PointGravity(PointGravity),
+    TurnToDirectionEnabled(TurnToDirectionEnabled),
+    InfiniteEmitEnabled(InfiniteEmitEnabled),
+    /// Behavior with an ID this crate doesn't know. `data` starts at the behavior's header and
+    /// covers the size of the largest known behavior, or up to the end of the file.
+    Unknown { id: u16, data: Vec<u8> },
+}
+
+impl BehaviorKind {
+    /// Add this behavior's parameters to an emitter's settings
+    pub fn apply(&self, settings: &mut EmitterSettings) {
+        match self {
+            Self::Basic(b) => {
+                settings.priority = b.priority;
+                settings.max_particles = b.maximum_particle;
+                settings.emit_count = b.attime_create;
+                settings.interval = b.interval;
+                settings.lifetime = b.lifetime;
+                settings.speed = (b.speed_min, b.speed_max - b.speed_min);
+                settings.particle_life = (b.lifespan_min as f32, b.lifespan_max as f32 - b.lifespan_min as f32);
+                settings.angle = ((b.angle + 90.).to_radians(), b.angle_variance.to_radians());
+            },
+            Self::RndSeedChange(b) => settings.seed = Some(b.seed),
+            Self::Delay(b) => settings.delay = b.delay_time,
+            Self::Gravity(b) => settings.gravity = Some(Vec2::new(b.gravity_x, b.gravity_y)),
+            Self::Position(b) => settings.offset = Some((Vec2::new(b.offset_x_min, b.offset_y_min),
+                Vec2::new(b.offset_x_max - b.offset_x_min, b.offset_y_max - b.offset_y_min))),
+            Self::Rotation(b) => settings.rotation = Some((
+                (b.rotation_min, b.rotation_max - b.rotation_min),
+                (b.rotation_add_min, b.rotation_add_max - b.rotation_add_min))),
+            Self::TransRotation(b) => settings.rotation_trans = Some((b.rotation_factor, b.end_life_time_per / 100.)),
+            Self::TransSpeed(b) => settings.trans_speed = Some((b.speed_min, b.speed_max - b.speed_min)),
+            Self::TangentialAcceleration(b) => settings.tangential_acceleration =
+                Some((b.acceleration_min, b.acceleration_max - b.acceleration_min)),
+            Self::InitColor(b) => settings.init_color = Some(color_range(b.color_min, b.color_max)),
+            Self::TransColor(b) => settings.trans_color = Some(color_range(b.color_min, b.color_max)),
+            Self::AlphaFade(b) => settings.alpha_fade = Some((b.disprange_min, b.disprange_max)),
+            Self::Size(b) => settings.init_size = Some((Vec2::new(b.size_x_min, b.size_y_min),
+                Vec2::new(b.size_x_max - b.size_x_min, b.size_y_max - b.size_y_min),
+                (b.scale_factor_min, b.scale_factor_max - b.scale_factor_min))),
+            Self::TransSize(b) => settings.trans_size = Some((Vec2::new(b.size_x_min, b.size_y_min),
+                Vec2::new(b.size_x_max - b.size_x_min, b.size_y_max - b.size_y_min),
+                (b.scale_factor_min, b.scale_factor_max - b.scale_factor_min))),
+            Self::PointGravity(b) => settings.point_gravity = Some((Vec2::new(b.position_x, b.position_y), b.power)),
+            Self::TurnToDirectionEnabled(b) => settings.turn_to_direction = Some(b.rotation),
+            Self::InfiniteEmitEnabled(b) => settings.infinite = b.flag != 0,
+            Self::Unknown { .. } => ()
+        }
+    }
+
+    pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
+        match self {
+            Self::Basic(b) => b.to_xml(writer),
+            Self::RndSeedChange(b) => b.to_xml(writer),
+            Self::Delay(b) => b.to_xml(writer),
+            Self::Gravity(b) => b.to_xml(writer),
+            Self::Position(b) => b.to_xml(writer),
+            Self::Rotation(b) => b.to_xml(writer),
+            Self::TransRotation(b) => b.to_xml(writer),
+            Self::TransSpeed(b) => b.to_xml(writer),
+            Self::TangentialAcceleration(b) => b.to_xml(writer),
+            Self::InitColor(b) => b.to_xml(writer),
+            Self::TransColor(b) => b.to_xml(writer),
+            Self::AlphaFade(b) => b.to_xml(writer),
+            Self::Size(b) => b.to_xml(writer),
+            Self::TransSize(b) => b.to_xml(writer),
+            Self::PointGravity(b) => b.to_xml(writer),
+            Self::TurnToDirectionEnabled(b) => b.to_xml(writer),
+            Self::InfiniteEmitEnabled(b) => b.to_xml(writer),
+            // SpriteStudio wouldn't be able to load it
+            Self::Unknown { .. } => Ok(()),
+        }
     }
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Basic {
     _super: Behavior,
     priority: u32,
@@ -110,6 +276,10 @@ pub struct Basic {
     angle_variance: f32
 }
 
+unsafe impl BinaryStruct for Basic {
+    const NAME: &'static str = "Basic";
+}
+
 impl Basic {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -148,12 +318,17 @@ impl Basic {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RndSeedChange {
     _super: Behavior,
     seed: u32
 }
 
+unsafe impl BinaryStruct for RndSeedChange {
+    const NAME: &'static str = "RndSeedChange";
+}
+
 impl RndSeedChange {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -170,12 +345,17 @@ impl RndSeedChange {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Delay {
     _super: Behavior,
     delay_time: u32
 }
 
+unsafe impl BinaryStruct for Delay {
+    const NAME: &'static str = "Delay";
+}
+
 impl Delay {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -192,13 +372,18 @@ impl Delay {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Gravity {
     _super: Behavior,
     gravity_x: f32,
     gravity_y: f32,
 }
 
+unsafe impl BinaryStruct for Gravity {
+    const NAME: &'static str = "Gravity";
+}
+
 impl Gravity {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -215,7 +400,8 @@ impl Gravity {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Position {
     _super: Behavior,
     offset_x_min: f32,
@@ -224,6 +410,10 @@ pub struct Position {
     offset_y_max: f32,
 }
 
+unsafe impl BinaryStruct for Position {
+    const NAME: &'static str = "Position";
+}
+
 impl Position {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -248,7 +438,8 @@ impl Position {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Rotation {
     _super: Behavior,
     rotation_min: f32,
@@ -257,6 +448,10 @@ pub struct Rotation {
     rotation_add_max: f32,
 }
 
+unsafe impl BinaryStruct for Rotation {
+    const NAME: &'static str = "Rotation";
+}
+
 impl Rotation {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -281,13 +476,18 @@ impl Rotation {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TransRotation {
     _super: Behavior,
     rotation_factor: f32,
     end_life_time_per: f32
 }
 
+unsafe impl BinaryStruct for TransRotation {
+    const NAME: &'static str = "TransRotation";
+}
+
 impl TransRotation {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -306,13 +506,18 @@ impl TransRotation {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TransSpeed {
     _super: Behavior,
     speed_min: f32,
     speed_max: f32
 }
 
+unsafe impl BinaryStruct for TransSpeed {
+    const NAME: &'static str = "TransSpeed";
+}
+
 impl TransSpeed {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -332,13 +537,18 @@ impl TransSpeed {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TangentialAcceleration {
     _super: Behavior,
     acceleration_min: f32,
     acceleration_max: f32
 }
 
+unsafe impl BinaryStruct for TangentialAcceleration {
+    const NAME: &'static str = "TangentialAcceleration";
+}
+
 impl TangentialAcceleration {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -358,13 +568,18 @@ impl TangentialAcceleration {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InitColor {
     _super: Behavior,
     color_min: u32,
     color_max: u32
 }
 
+unsafe impl BinaryStruct for InitColor {
+    const NAME: &'static str = "InitColor";
+}
+
 impl InitColor {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -384,13 +599,18 @@ impl InitColor {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TransColor {
     _super: Behavior,
     color_min: u32,
     color_max: u32
 }
 
+unsafe impl BinaryStruct for TransColor {
+    const NAME: &'static str = "TransColor";
+}
+
 impl TransColor {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -410,13 +630,18 @@ impl TransColor {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AlphaFade {
     _super: Behavior,
     disprange_min: f32,
     disprange_max: f32
 }
 
+unsafe impl BinaryStruct for AlphaFade {
+    const NAME: &'static str = "AlphaFade";
+}
+
 impl AlphaFade {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -436,7 +661,8 @@ impl AlphaFade {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Size {
     _super: Behavior,
     size_x_min: f32,
@@ -447,6 +673,10 @@ pub struct Size {
     scale_factor_max: f32,
 }
 
+unsafe impl BinaryStruct for Size {
+    const NAME: &'static str = "Size";
+}
+
 impl Size {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -476,7 +706,8 @@ impl Size {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TransSize {
     _super: Behavior,
     size_x_min: f32,
@@ -487,6 +718,10 @@ pub struct TransSize {
     scale_factor_max: f32,
 }
 
+unsafe impl BinaryStruct for TransSize {
+    const NAME: &'static str = "TransSize";
+}
+
 impl TransSize {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -516,7 +751,8 @@ impl TransSize {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PointGravity {
     _super: Behavior,
     position_x: f32,
@@ -524,6 +760,10 @@ pub struct PointGravity {
     power: f32,
 }
 
+unsafe impl BinaryStruct for PointGravity {
+    const NAME: &'static str = "PointGravity";
+}
+
 impl PointGravity {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -542,12 +782,17 @@ impl PointGravity {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TurnToDirectionEnabled {
     _super: Behavior,
     rotation: f32,
 }
 
+unsafe impl BinaryStruct for TurnToDirectionEnabled {
+    const NAME: &'static str = "TurnToDirectionEnabled";
+}
+
 impl TurnToDirectionEnabled {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -564,12 +809,17 @@ impl TurnToDirectionEnabled {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InfiniteEmitEnabled {
     _super: Behavior,
     flag: u32,
 }
 
+unsafe impl BinaryStruct for InfiniteEmitEnabled {
+    const NAME: &'static str = "InfiniteEmitEnabled";
+}
+
 impl InfiniteEmitEnabled {
     pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("value")
@@ -597,6 +847,18 @@ pub struct Node {
     behaviors: Ptr<Ptr<Behavior>>
 }
 
+unsafe impl BinaryStruct for Node {
+    const NAME: &'static str = "Node";
+    fn validate(binary: &[u8], offset: usize) -> Result<(), ParseError> {
+        check_enum("EffectNodeType", binary, offset + std::mem::offset_of!(Node, _type), EffectNodeType::Particle as u16)?;
+        check_enum("RenderBlendType", binary, offset + std::mem::offset_of!(Node, blend_type), RenderBlendType::Add as u16)
+    }
+}
+
+unsafe impl BinaryStruct for Ptr<Behavior> {
+    const NAME: &'static str = "Ptr<Behavior>";
+}
+
 impl Node {
     pub fn get_array_index(&self) -> i16 {
         self.array_index
@@ -613,50 +875,79 @@ impl Node {
     pub fn get_blend_type(&self) -> RenderBlendType {
         self.blend_type
     }
-    pub fn get_behaviors(&self, binary: &[u8]) -> &[Ptr<Behavior>] {
+    pub fn get_behaviors<'a>(&self, binary: &'a [u8]) -> Result<&'a [Ptr<Behavior>], ParseError> {
         self.behaviors.array(binary, self.num_behavior as usize)
     }
-    pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>,
-        binary: &[u8], cells: &[CellEntry], tracker: &mut NodeTracker) -> std::io::Result<()> {
-        let effect_name = match self._type {
+    pub fn to_model(&self, binary: &[u8], cells: &[CellEntry], tracker: &mut NodeTracker)
+        -> Result<model::EffectNode, Box<dyn Error>> {
+        let name = match self._type {
             EffectNodeType::Root => "Root".to_string(),
             EffectNodeType::Emmiter => format!("Emitter_{}", tracker.get_emitter()),
             EffectNodeType::Particle => format!("Particle_{}", tracker.get_particle()),
         };
+        let cell = match self.cell_index {
+            -1 => None,
+            v => {
+                let c = cells.get(v as usize).ok_or(EffectError::CellIndexOutOfRange(v))?;
+                let map = c.get_cell_map(binary)?;
+                Some(model::CellRef {
+                    map_index: map.get_index(),
+                    map_name: map.get_name(binary)?.to_string(),
+                    name: c.get_name(binary)?.to_string()
+                })
+            }
+        };
+        let mut behaviors = Vec::with_capacity(self.num_behavior as usize);
+        for p_behavior in self.get_behaviors(binary)? {
+            behaviors.push(p_behavior.to_kind(binary)?);
+        }
+        Ok(model::EffectNode {
+            name,
+            array_index: self.array_index,
+            parent_index: self.parent_index,
+            node_type: self._type,
+            cell,
+            blend_type: self.blend_type,
+            behaviors
+        })
+    }
+}
+
+impl model::EffectNode {
+    pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("name")
-            .write_text_content(BytesText::new(&effect_name))?;
+            .write_text_content(BytesText::new(&self.name))?;
         writer.create_element("type")
-            .write_text_content(BytesText::new(&format!("{:?}", self._type)))?;
+            .write_text_content(BytesText::new(&format!("{:?}", self.node_type)))?;
         writer.create_element("arrayIndex")
             .write_text_content(BytesText::new(&format!("{}", self.array_index)))?;
         writer.create_element("parentIndex")
             .write_text_content(BytesText::new(&format!("{}", self.parent_index)))?;
         writer.create_element("visible")
             .write_text_content(BytesText::new("1"))?;
-        if self._type == EffectNodeType::Root {
+        if self.node_type == EffectNodeType::Root {
             return Ok(());
         }
-
         writer.create_element("behavior")
         .write_inner_content(|writer| {
-            let cell_name = match self.cell_index {
-                -1 => "", v => cells[v as usize].get_name(binary)
+            let cell_name = match &self.cell {
+                None => "", Some(c) => c.name.as_str()
             };
             writer.create_element("CellName")
                 .write_text_content(BytesText::new(cell_name))?;
-            let cell_map_name = match self.cell_index {
-                -1 => String::new(), v => format!("{}.ssce", cells[v as usize].get_cell_map(binary).get_name(binary))
+            let cell_map_name = match &self.cell {
+                None => String::new(), Some(c) => format!("{}.ssce", c.map_name)
             };
             writer.create_element("CellMapName")
                 .write_text_content(BytesText::new(&cell_map_name))?;
             writer.create_element("BlendType")
                 .write_text_content(BytesText::new(&format!("{:?}", self.blend_type)))?;
             let list = writer.create_element("list");
-            match self.num_behavior {
+            match self.behaviors.len() {
                 0 => list.write_empty(),
                 _ => list.write_inner_content(|writer| {
-                    for p_behavior in self.get_behaviors(binary) {
-                        p_behavior.value(binary).to_xml(writer, binary)?;
+                    for behavior in &self.behaviors {
+                        behavior.to_xml(writer)?;
                     }
                     Ok(())
                 })
@@ -673,6 +964,12 @@ pub struct NodeTracker {
     particles: usize
 }
 
+impl Default for NodeTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NodeTracker {
     pub fn new() -> Self {
         Self {
@@ -705,9 +1002,13 @@ pub struct Effect {
     nodes: Ptr<Node>
 }
 
+unsafe impl BinaryStruct for Effect {
+    const NAME: &'static str = "Effect";
+}
+
 impl Effect {
-    pub fn get_name(&self, binary: &[u8]) -> &str {
-        self.name.value(binary)
+    pub fn get_name<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.name.value(binary, "Effect.name")
     }
     pub fn get_fps(&self) -> u16 {
         self.fps
@@ -724,25 +1025,98 @@ impl Effect {
     pub fn get_layout_scale_y(&self) -> u16 {
         self.layout_scale_y
     }
-    pub fn get_nodes(&self, binary: &[u8]) -> &[Node] {
+    pub fn get_nodes<'a>(&self, binary: &'a [u8]) -> Result<&'a [Node], ParseError> {
         self.nodes.array(binary, self.num_node_list as usize)
     }
 
-    pub fn to_xml(&self, binary: &[u8], cells: &[CellEntry]) -> Result<Vec<u8>, Box<dyn Error>> {
+    /// Check that the nodes form a tree under a single root, and get the order SpriteStudio lists
+    /// them in: each node followed by its children. Parents must come before their children, so
+    /// there can't be any cycles.
+    pub fn get_node_order(&self, binary: &[u8]) -> Result<Vec<usize>, Box<dyn Error>> {
+        let nodes = self.get_nodes(binary)?;
+        if nodes.is_empty() {
+            return Ok(vec![]);
+        }
+        if nodes[0]._type != EffectNodeType::Root || nodes[0].parent_index != -1 {
+            return Err(Box::new(EffectError::MissingRootNode));
+        }
+        let mut children: Vec<Vec<usize>> = vec![vec![]; nodes.len()];
+        for (i, node) in nodes.iter().enumerate().skip(1) {
+            let parent = usize::try_from(node.parent_index).ok().filter(|p| *p < i)
+                .ok_or(EffectError::InvalidNodeParent(i, node.parent_index))?;
+            let parent_type = nodes[parent]._type;
+            let valid = match node._type {
+                EffectNodeType::Root => false,
+                EffectNodeType::Emmiter => parent_type != EffectNodeType::Emmiter,
+                EffectNodeType::Particle => parent_type == EffectNodeType::Emmiter,
+            };
+            if !valid {
+                return Err(Box::new(EffectError::InvalidNodeNesting(i, node._type, parent_type)));
+            }
+            children[parent].push(i);
+        }
+        let mut order = Vec::with_capacity(nodes.len());
+        let mut stack = vec![0];
+        while let Some(i) = stack.pop() {
+            order.push(i);
+            stack.extend(children[i].iter().rev());
+        }
+        Ok(order)
+    }
+
+    /// SSBP files don't keep node names, so nodes are named by type in the order SpriteStudio
+    /// lists them
+    pub fn to_model(&self, binary: &[u8], cells: &[CellEntry], warnings: &mut Vec<model::Warning>)
+        -> Result<model::Effect, Box<dyn Error>> {
+        let name = self.get_name(binary)?;
+        let mut tracker = NodeTracker::new();
+        let binary_nodes = self.get_nodes(binary)?;
+        let order = self.get_node_order(binary)?;
+        // position of each node once it's been sorted
+        let mut new_index = vec![0; order.len()];
+        for (position, i) in order.iter().enumerate() {
+            new_index[*i] = position as i16;
+        }
+        let mut nodes = Vec::with_capacity(self.num_node_list as usize);
+        for i in order {
+            let mut node = binary_nodes[i].to_model(binary, cells, &mut tracker)?;
+            node.array_index = new_index[i];
+            node.parent_index = usize::try_from(node.parent_index).map_or(-1, |p| new_index[p]);
+            for behavior in &node.behaviors {
+                if let BehaviorKind::Unknown { id, .. } = behavior {
+                    warnings.push(model::Warning::UnknownBehavior { effect: name.to_string(),
+                        node: node.name.clone(), id: *id });
+                }
+            }
+            nodes.push(node);
+        }
+        Ok(model::Effect {
+            name: name.to_string(),
+            fps: self.fps,
+            is_lock_random_seed: self.is_lock_random_seed,
+            lock_random_seed: self.lock_random_seed,
+            layout_scale_x: self.layout_scale_x,
+            layout_scale_y: self.layout_scale_y,
+            nodes
+        })
+    }
+}
+
+impl model::Effect {
+    pub fn to_xml(&self) -> Result<Vec<u8>, Box<dyn Error>> {
         let xml_fmt = "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n";
         let mut cursor = Cursor::new(xml_fmt.as_bytes().to_vec());
         cursor.seek(SeekFrom::End(0))?;
-        let mut writer = Writer::new_with_indent(cursor, '\t' as u8, 1);
+        let mut writer = Writer::new_with_indent(cursor, b'\t', 1);
         writer.create_element("SpriteStudioEffect")
             .with_attributes([("version", "2.00.00")])
-            .write_inner_content(|writer| self.to_xml_body(writer, binary, cells))?;
+            .write_inner_content(|writer| self.to_xml_body(writer))?;
         Ok(writer.into_inner().into_inner())
     }
 
-    pub(crate) fn to_xml_body<W: Write + Seek>(&self, writer: &mut Writer<W>,
-        binary: &[u8], cells: &[CellEntry]) -> std::io::Result<()> {
+    pub(crate) fn to_xml_body<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
         writer.create_element("name")
-            .write_text_content(BytesText::new(self.get_name(binary)))?;
+            .write_text_content(BytesText::new(&self.name))?;
         create_blank_element(writer, "exportPath")?;
         writer.create_element("effectData")
             .write_inner_content(|writer| {
@@ -752,19 +1126,20 @@ impl Effect {
                     .write_text_content(BytesText::new(&format!("{}", self.is_lock_random_seed)))?;
                 writer.create_element("fps")
                     .write_text_content(BytesText::new(&format!("{}", self.fps)))?;
+                // the preview background and render version aren't stored in SSBP files
                 writer.create_element("bgColor")
                     .write_text_content(BytesText::new("FF000000"))?;
+                writer.create_element("layoutScaleX")
+                    .write_text_content(BytesText::new(&format!("{}", self.layout_scale_x)))?;
+                writer.create_element("layoutScaleY")
+                    .write_text_content(BytesText::new(&format!("{}", self.layout_scale_y)))?;
                 writer.create_element("renderVersion")
                     .write_text_content(BytesText::new("2"))?;
                 writer.create_element("nodeList")
                     .write_inner_content(|writer| {
-                        let mut tracker = NodeTracker::new();
-                        for node in self.get_nodes(binary) {
+                        for node in &self.nodes {
                             writer.create_element("node")
-                                .write_inner_content(|writer| {
-                                    node.to_xml(writer, binary, cells, &mut tracker)?;
-                                    Ok(())
-                                })?;
+                                .write_inner_content(|writer| node.to_xml(writer))?;
                         }
                         Ok(())
                     })?;
@@ -772,4 +1147,86 @@ impl Effect {
             })?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::{Project, Warning};
+    use crate::project::SsbpFile;
+    use crate::test_data::{write_effect_project, TestNode};
+    use crate::util::ParseError;
+    use super::{BehaviorKind, EffectError, EffectNodeType};
+
+    fn root(behaviors: Vec<Vec<u8>>) -> TestNode {
+        TestNode { parent: -1, node_type: EffectNodeType::Root, behaviors }
+    }
+
+    fn node(parent: i16, node_type: EffectNodeType) -> TestNode {
+        TestNode { parent, node_type, behaviors: vec![] }
+    }
+
+    fn node_order(nodes: &[TestNode]) -> Result<Vec<usize>, EffectError> {
+        let binary = write_effect_project(nodes);
+        let file = SsbpFile::parse(binary.bytes()).unwrap();
+        let effect = &file.get_header().get_effects(binary.bytes()).unwrap()[0];
+        effect.get_node_order(binary.bytes()).map_err(|e| *e.downcast::<EffectError>().unwrap())
+    }
+
+    #[test]
+    fn nodes_are_ordered_depth_first() {
+        let order = node_order(&[
+            root(vec![]),
+            node(0, EffectNodeType::Emmiter),
+            node(0, EffectNodeType::Emmiter),
+            node(1, EffectNodeType::Particle),
+        ]);
+        assert_eq!(order.unwrap(), vec![0, 1, 3, 2]);
+    }
+
+    #[test]
+    fn first_node_must_be_root() {
+        let order = node_order(&[node(-1, EffectNodeType::Emmiter)]);
+        assert!(matches!(order, Err(EffectError::MissingRootNode)), "{:?}", order);
+    }
+
+    #[test]
+    fn parent_must_come_before_child() {
+        let order = node_order(&[
+            root(vec![]),
+            node(2, EffectNodeType::Emmiter),
+            node(0, EffectNodeType::Emmiter),
+        ]);
+        assert!(matches!(order, Err(EffectError::InvalidNodeParent(1, 2))), "{:?}", order);
+    }
+
+    #[test]
+    fn particle_must_be_under_emitter() {
+        let order = node_order(&[root(vec![]), node(0, EffectNodeType::Particle)]);
+        assert!(matches!(order,
+            Err(EffectError::InvalidNodeNesting(1, EffectNodeType::Particle, EffectNodeType::Root))), "{:?}", order);
+    }
+
+    #[test]
+    fn unknown_behaviors_are_kept_and_reported() {
+        let behavior = vec![99, 0, 0, 0, 1, 2, 3, 4];
+        let binary = write_effect_project(&[root(vec![behavior.clone()])]);
+        let project = Project::from_file(&SsbpFile::parse(binary.bytes()).unwrap()).unwrap();
+        match &project.effects[0].nodes[0].behaviors[..] {
+            [BehaviorKind::Unknown { id: 99, data }] => assert!(data.starts_with(&behavior)),
+            b => panic!("expected one unknown behavior, got {:?}", b),
+        }
+        assert_eq!(project.warnings, vec![Warning::UnknownBehavior {
+            effect: "effect".to_string(), node: "Root".to_string(), id: 99
+        }]);
+    }
+
+    #[test]
+    fn undersized_behavior_is_out_of_bounds() {
+        // Gravity needs 12 bytes, but only the ID fits before the end of the file
+        let binary = write_effect_project(&[root(vec![vec![4, 0]])]);
+        match SsbpFile::parse(binary.bytes()) {
+            Err(ParseError::OutOfBounds { name: "Gravity", .. }) => (),
+            r => panic!("expected Gravity to be out of bounds, got {:?}", r.err()),
+        }
+    }
+}
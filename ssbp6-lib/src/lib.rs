@@ -1,5 +1,13 @@
 pub mod anime;
 pub mod cell;
+pub mod curve;
 pub mod effect;
+pub mod model;
+pub mod particle;
 pub mod project;
+pub mod reduce;
+pub mod revision;
+pub mod sequence;
+#[cfg(test)]
+mod test_data;
 pub mod util;
\ No newline at end of file
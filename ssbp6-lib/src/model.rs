@@ -0,0 +1,504 @@
+// Owned representation of an SSBP project. Every pointer in the binary is resolved and the frame
+// stream is baked into keyframe tracks when the project is decoded, so exporters can work from
+// this model without access to the original file.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use glam::{UVec2, UVec3, Vec2};
+use crate::anime::{AttributeKeyframe, BlendType, BoundsType, PartType};
+use crate::cell::{InterpolateType, TexFilterMode, TexWrapMode};
+use crate::effect::{BehaviorKind, EffectNodeType, RenderBlendType};
+use crate::project::SsbpFile;
+use crate::sequence::SequenceType;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Project {
+    pub cell_maps: Vec<CellMap>,
+    pub anime_packs: Vec<AnimePack>,
+    pub effects: Vec<Effect>,
+    pub sequence_packs: Vec<SequencePack>,
+    /// Parts of the file that couldn't be converted exactly
+    pub warnings: Vec<Warning>,
+}
+
+/// Data that's skipped or converted approximately. Conversion carries on past these, so they're
+/// collected on [`Project`] for the caller to report.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Warning {
+    /// A frame uses a cell index that isn't in the cell table, so its CELL key is skipped
+    MissingCell { animation: String, part: String, frame: usize, cell_index: u16 },
+    /// A frame's mesh vertices don't match the part's mesh, so its DEFM key is skipped
+    MismatchedMesh { animation: String, part: String, frame: usize, vertices: usize },
+    /// An instance part plays an animation that isn't in the file, so its IPRM keys count frames
+    /// from the start of the animation instead of its labels
+    MissingAnimation { anime_pack: String, part: String, ref_name: String },
+    /// An effect node has a behavior ID this crate doesn't know, so it's left out of the .ssee
+    UnknownBehavior { effect: String, node: String, id: u16 },
+    /// An animation shows an effect made for a different fps, so it plays at a different speed in
+    /// SpriteStudio
+    MismatchedFps { effect: String, effect_fps: u16, anime_pack: String, animation: String, animation_fps: u16 },
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingCell { animation, part, frame, cell_index } =>
+                write!(f, "{} frame {}, part {}: cell index {} doesn't exist", animation, frame, part, cell_index),
+            Self::MismatchedMesh { animation, part, frame, vertices } =>
+                write!(f, "{} frame {}, part {}: no mesh with {} vertices to deform", animation, frame, part, vertices),
+            Self::MissingAnimation { anime_pack, part, ref_name } =>
+                write!(f, "{} instance part {}: animation {} doesn't exist", anime_pack, part, ref_name),
+            Self::UnknownBehavior { effect, node, id } =>
+                write!(f, "{} node {}: unknown behavior {} isn't exported", effect, node, id),
+            Self::MismatchedFps { effect, effect_fps, anime_pack, animation, animation_fps } =>
+                write!(f, "{}/{} ({} fps) shows effect {} made for {} fps", anime_pack, animation,
+                    animation_fps, effect, effect_fps),
+        }
+    }
+}
+
+impl Project {
+    pub fn get_anime_settings(&self) -> AnimeSettings {
+        AnimeSettings::from_animations(self.anime_packs.iter().flat_map(|p| p.animations.iter()))
+    }
+
+    pub fn from_file(file: &SsbpFile) -> Result<Self, Box<dyn Error>> {
+        let binary = file.get_binary();
+        let header = file.get_header();
+        let layout = file.get_layout();
+        let cells = header.get_cells(binary)?;
+        let texture_sizes = header.get_texture_sizes(binary)?;
+        let mut cell_maps: Vec<CellMap> = vec![];
+        // cell array index -> (map index, cell name) (for getting reference in ssae)
+        let mut cell_resolver: HashMap<usize, (u16, &str)> = HashMap::new();
+        for (i, entry) in cells.iter().enumerate() {
+            let map = entry.get_cell_map(binary)?;
+            let cell = entry.to_model(binary, texture_sizes.get(&map.get_index()).copied())?;
+            match cell_maps.iter_mut().find(|m| m.index == map.get_index()) {
+                Some(cell_map) => cell_map.cells.push(cell),
+                None => cell_maps.push(map.to_model(binary, vec![cell])?)
+            };
+            cell_resolver.insert(i, (map.get_index(), entry.get_name(binary)?));
+        }
+        cell_maps.sort_by_key(|m| m.index);
+        let mut warnings = vec![];
+        let mut anime_packs = Vec::with_capacity(header.get_num_anime() as usize);
+        for anime in header.get_anime(binary)? {
+            anime_packs.push(anime.to_model(file, &cell_resolver, &texture_sizes, &mut warnings)?);
+        }
+        // mesh geometry is only stored on the animations of mesh parts, so give it to the cell
+        // they use
+        for anime in header.get_anime(binary)? {
+            let parts = anime.get_parts(binary, &layout)?;
+            for anim in anime.get_anims(binary, &layout)? {
+                let defaults = anim.get_default_data(binary, parts.len(), &layout)?;
+                for (i, _) in parts.iter().enumerate().filter(|(_, p)| p.get_type() == PartType::mesh) {
+                    let cell_index = defaults[i].get_cell_index() as usize;
+                    let (Some(entry), Some((map_index, name))) = (cells.get(cell_index), cell_resolver.get(&cell_index)) else {
+                        continue;
+                    };
+                    let cell = cell_maps.iter_mut().find(|m| m.index == *map_index)
+                        .and_then(|m| m.cells.iter_mut().find(|c| c.name == *name));
+                    if let Some(cell) = cell.filter(|c| c.mesh.is_none()) {
+                        let rotated = cell.rotated;
+                        cell.mesh = Some(CellMesh {
+                            points: anim.get_mesh_uv(binary, parts.len())?[i].get_uvs(binary)?
+                                .iter().map(|uv| entry.uv_to_point(*uv, rotated)).collect(),
+                            triangles: anim.get_mesh_index(binary, parts.len())?[i].get_triangles(binary)?.to_vec(),
+                        });
+                    }
+                }
+            }
+        }
+        let mut effects = Vec::with_capacity(header.get_num_effects() as usize);
+        for effect in header.get_effects(binary)? {
+            effects.push(effect.to_model(binary, cells, &mut warnings)?);
+        }
+        // effects play at their own fps, so one made for a different rate than the animation
+        // that shows it plays at a different speed in SpriteStudio
+        for anime_pack in &anime_packs {
+            for part in anime_pack.parts.iter().filter(|p| p.part_type == PartType::effect) {
+                let Some(effect) = effects.iter().find(|e| e.name == part.effect_name) else {
+                    continue;
+                };
+                for animation in anime_pack.animations.iter().filter(|a| !a.is_setup() && a.fps != effect.fps) {
+                    let uses_effect = animation.part_animes.iter().any(|p| p.part_name == part.name
+                        && p.attributes.iter().any(|a| a.tag == "EFCT"));
+                    if uses_effect {
+                        warnings.push(Warning::MismatchedFps {
+                            effect: effect.name.clone(), effect_fps: effect.fps,
+                            anime_pack: anime_pack.name.clone(), animation: animation.name.clone(),
+                            animation_fps: animation.fps
+                        });
+                    }
+                }
+            }
+        }
+        let sequence_packs = header.get_sequence_packs(binary)?.iter()
+            .map(|p| p.to_model(binary)).collect::<Result<_, _>>()?;
+        Ok(Self { cell_maps, anime_packs, effects, sequence_packs, warnings })
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CellMap {
+    pub name: String,
+    pub image_path: String,
+    pub index: u16,
+    pub wrap_mode: TexWrapMode,
+    pub filter_mode: TexFilterMode,
+    pub cells: Vec<Cell>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cell {
+    pub name: String,
+    pub index: u16,
+    pub position: UVec2,
+    pub size: UVec2,
+    pub pivot: Vec2,
+    /// Stored rotated 90° clockwise in the texture
+    pub rotated: bool,
+    pub texcoord: [Vec2; 2],
+    pub mesh: Option<CellMesh>,
+}
+
+/// Mesh that a cell is split into when it's used by a mesh part
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CellMesh {
+    /// Vertex positions in pixels from the top left of the cell
+    pub points: Vec<Vec2>,
+    pub triangles: Vec<UVec3>,
+}
+
+/// Reference to a cell from an animation or effect
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CellRef {
+    pub map_index: u16,
+    pub map_name: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AnimePack {
+    pub name: String,
+    pub parts: Vec<Part>,
+    pub animations: Vec<Animation>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Part {
+    pub name: String,
+    pub index: i16,
+    pub parent_index: i16,
+    pub part_type: PartType,
+    pub bounds_type: BoundsType,
+    pub alpha_blend_type: BlendType,
+    pub ref_name: String,
+    pub effect_name: String,
+    pub color_label: String,
+    pub mask_influence: u16,
+    /// Only set for armature parts
+    pub bone: Option<Bone>,
+    pub inheritance: Inheritance,
+}
+
+/// Which of the parent's states a part takes on as well as its own. SSBP files don't store this,
+/// so it's inferred from the frame data when an anime pack is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Inheritance {
+    pub opacity: bool,
+    /// Image flips (IFLH/IFLV) aren't stored in SSBP files, so these stay at their defaults
+    pub image_flip_h: bool,
+    pub image_flip_v: bool,
+    pub flip_h: bool,
+    pub flip_v: bool,
+    pub hide: bool,
+}
+
+impl Default for Inheritance {
+    /// The same as a new part in SpriteStudio, which inherits everything except image flips
+    fn default() -> Self {
+        Self { opacity: true, image_flip_h: false, image_flip_v: false, flip_h: true, flip_v: true, hide: true }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bone {
+    pub length: f32,
+    pub position: Vec2,
+    pub rotation: f32,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Animation {
+    pub name: String,
+    pub start_frame: u16,
+    pub end_frame: u16,
+    pub total_frames: u16,
+    pub fps: u16,
+    pub canvas_size: UVec2,
+    pub canvas_pivot: Vec2,
+    pub labels: Vec<Label>,
+    pub part_animes: Vec<PartAnime>,
+}
+
+impl Animation {
+    pub fn is_setup(&self) -> bool {
+        self.name == "Setup"
+    }
+
+    pub fn get_settings(&self) -> AnimeSettings {
+        AnimeSettings {
+            fps: self.fps,
+            start_frame: self.start_frame,
+            end_frame: self.end_frame,
+            total_frames: self.total_frames,
+            canvas_size: self.canvas_size,
+            canvas_pivot: self.canvas_pivot,
+        }
+    }
+}
+
+/// Playback and canvas settings shared by the animations in a project or anime pack. SSBP files
+/// only store these per animation, so shared settings are taken from the most common values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AnimeSettings {
+    pub fps: u16,
+    pub start_frame: u16,
+    pub end_frame: u16,
+    pub total_frames: u16,
+    pub canvas_size: UVec2,
+    pub canvas_pivot: Vec2,
+}
+
+impl Default for AnimeSettings {
+    /// Settings of a new SpriteStudio project
+    fn default() -> Self {
+        Self {
+            fps: 30,
+            start_frame: 0,
+            end_frame: 10,
+            total_frames: 11,
+            canvas_size: UVec2::new(320, 320),
+            canvas_pivot: Vec2::ZERO,
+        }
+    }
+}
+
+fn most_common<T: PartialEq + Copy>(values: impl Iterator<Item = T>) -> Option<T> {
+    let mut counts: Vec<(T, usize)> = vec![];
+    for value in values {
+        match counts.iter_mut().find(|(v, _)| *v == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1))
+        }
+    }
+    // earliest value wins a tie
+    counts.into_iter().rev().max_by_key(|(_, count)| *count).map(|(v, _)| v)
+}
+
+impl AnimeSettings {
+    /// Pick the most common fps, frame range and canvas among the animations. Setup animations
+    /// are skipped since they only hold a single frame.
+    pub fn from_animations<'a>(animations: impl Iterator<Item = &'a Animation> + Clone) -> Self {
+        let animations = animations.filter(|a| !a.is_setup());
+        let default = Self::default();
+        let (start_frame, end_frame, total_frames) = most_common(animations.clone()
+            .map(|a| (a.start_frame, a.end_frame, a.total_frames)))
+            .unwrap_or((default.start_frame, default.end_frame, default.total_frames));
+        let (canvas_size, canvas_pivot) = most_common(animations.clone()
+            .map(|a| (a.canvas_size, a.canvas_pivot)))
+            .unwrap_or((default.canvas_size, default.canvas_pivot));
+        Self {
+            fps: most_common(animations.map(|a| a.fps)).unwrap_or(default.fps),
+            start_frame,
+            end_frame,
+            total_frames,
+            canvas_size,
+            canvas_pivot,
+        }
+    }
+}
+
+impl AnimePack {
+    pub fn get_settings(&self) -> AnimeSettings {
+        AnimeSettings::from_animations(self.animations.iter())
+    }
+}
+
+/// Named frame within an animation
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Label {
+    pub name: String,
+    pub time: u16,
+}
+
+/// Keyframe tracks for a single part within an animation
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PartAnime {
+    pub part_name: String,
+    pub attributes: Vec<Attribute>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Attribute {
+    pub tag: String,
+    pub keyframes: Vec<Keyframe>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Keyframe {
+    pub frame: usize,
+    /// Curve used between this keyframe and the next one
+    pub interpolation: InterpolateType,
+    /// Handles for hermite and bezier keyframes
+    pub curve: Option<KeyframeCurve>,
+    pub value: AttributeKeyframe,
+}
+
+/// Curve handles, written as `<curve>startTime startValue endTime endValue</curve>`. Bezier handles
+/// are offsets from the keyframes on either side, while hermite handles are the values that the
+/// start and end tangents point towards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeyframeCurve {
+    pub start_time: f32,
+    pub start_value: f32,
+    pub end_time: f32,
+    pub end_value: f32,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Effect {
+    pub name: String,
+    pub fps: u16,
+    pub is_lock_random_seed: u16,
+    pub lock_random_seed: u16,
+    pub layout_scale_x: u16,
+    pub layout_scale_y: u16,
+    pub nodes: Vec<EffectNode>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EffectNode {
+    pub name: String,
+    pub array_index: i16,
+    pub parent_index: i16,
+    pub node_type: EffectNodeType,
+    pub cell: Option<CellRef>,
+    pub blend_type: RenderBlendType,
+    pub behaviors: Vec<BehaviorKind>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SequencePack {
+    pub name: String,
+    pub sequences: Vec<Sequence>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sequence {
+    pub name: String,
+    pub index: i16,
+    pub sequence_type: SequenceType,
+    pub items: Vec<SequenceItem>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SequenceItem {
+    pub anime_pack: String,
+    pub animation: String,
+    pub repeat_count: i16,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::project::SsbpFile;
+    use crate::sequence::SequenceType;
+    use crate::test_data::{write_empty_header, SsbpWriter};
+    use crate::util::ParseError;
+    use super::Project;
+
+    #[test]
+    fn sequence_packs_are_read_from_v12_files() {
+        let mut writer = SsbpWriter::default();
+        let [.., counts] = write_empty_header(&mut writer, 12);
+        writer.patch_u16(counts + 6, 1);
+        let pack_name = writer.string("sequences");
+        let sequence_name = writer.string("intro");
+        let anime_pack = writer.string("pack");
+        let animation = writer.string("anim");
+        let items = writer.u32(anime_pack);
+        writer.u32(animation);
+        writer.u16(3);
+        let sequences = writer.u32(sequence_name);
+        writer.u32(items);
+        // one item, index 0, looping back to the top
+        writer.u16(1);
+        writer.u16(0);
+        writer.u16(SequenceType::top as u16);
+        let packs = writer.u32(pack_name);
+        writer.u32(sequences);
+        writer.u16(1);
+        writer.patch(counts + 8, packs);
+        let binary = writer.finish();
+        let project = Project::from_file(&SsbpFile::parse(binary.bytes()).unwrap()).unwrap();
+        let [pack] = project.sequence_packs.as_slice() else { panic!("{:?}", project.sequence_packs) };
+        assert_eq!(pack.name, "sequences");
+        let [sequence] = pack.sequences.as_slice() else { panic!("{:?}", pack.sequences) };
+        assert_eq!((sequence.name.as_str(), sequence.index, sequence.sequence_type), ("intro", 0, SequenceType::top));
+        let [item] = sequence.items.as_slice() else { panic!("{:?}", sequence.items) };
+        assert_eq!((item.anime_pack.as_str(), item.animation.as_str(), item.repeat_count), ("pack", "anim", 3));
+        let xml = String::from_utf8(pack.to_xml().unwrap()).unwrap();
+        assert!(xml.contains("<type>top</type>"), "{}", xml);
+        assert!(xml.contains("<refAnime>anim</refAnime>"), "{}", xml);
+    }
+
+    #[test]
+    fn v11_files_have_no_sequence_packs() {
+        let mut writer = SsbpWriter::default();
+        let [.., counts] = write_empty_header(&mut writer, 11);
+        writer.patch_u16(counts + 6, 1);
+        // version 11 headers end at the counts, so this isn't a sequence pack pointer
+        writer.u32(0xfffffff0);
+        let binary = writer.finish();
+        let file = SsbpFile::parse(binary.bytes()).unwrap();
+        let project = Project::from_file(&file).unwrap();
+        assert_eq!(file.get_header().get_num_sequence_packs(), 1);
+        assert!(project.sequence_packs.is_empty());
+    }
+
+    #[test]
+    fn out_of_bounds_sequence_pack_table() {
+        let mut writer = SsbpWriter::default();
+        let [.., counts] = write_empty_header(&mut writer, 12);
+        writer.patch_u16(counts + 6, 1);
+        writer.patch(counts + 8, 0xfffffff0);
+        let binary = writer.finish();
+        let err = SsbpFile::parse(binary.bytes()).unwrap_err();
+        assert!(matches!(err, ParseError::OutOfBounds { name: "SequencePack", offset: 0xfffffff0, .. }), "{}", err);
+    }
+}
@@ -1,13 +1,17 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::io::Write;
 use std::io::{Cursor, Seek, SeekFrom};
 use quick_xml::events::BytesText;
+use glam::Vec2;
 use quick_xml::Writer;
-use crate::anime::Anime;
-use crate::anime::PartType::effect;
-use crate::cell::{tex_pack_settings_to_xml, CellEntry, InterpolateType, TexFilterMode, TexWrapMode};
+use crate::anime::{AnimEntry, Anime, PartType, AVAILABLE_ATTRIBUTES};
+use crate::cell::{infer_texture_size, tex_pack_settings_to_xml, CellEntry, InterpolateType, TexFilterMode, TexWrapMode};
 use crate::effect::Effect;
-use crate::util::{create_blank_element, create_name_list, to_xml_anime_settings, Ptr, StringPtr};
+use crate::model;
+use crate::revision::{Layout, Revision};
+use crate::sequence::SequencePack;
+use crate::util::{create_blank_element, create_name_list, to_xml_anime_settings, BinaryStruct, ParseError, Ptr, StringPtr};
 
 #[repr(C)]
 #[derive(Debug)]
@@ -29,32 +33,93 @@ const _: () = {
     ["Size of ProjectHeader"][size_of::<ProjectHeader>() - 0x24];
 };
 
+unsafe impl BinaryStruct for ProjectHeader {
+    const NAME: &'static str = "ProjectHeader";
+}
+
 impl ProjectHeader {
-    pub fn get_cells(&self, binary: &[u8]) -> &[CellEntry] {
+    pub fn get_data_id(&self) -> u32 {
+        self.data_id
+    }
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+    pub fn get_flags(&self) -> u32 {
+        self.flags
+    }
+    pub fn get_revision(&self) -> Result<Revision, ParseError> {
+        Revision::new(self.data_id, self.version)
+    }
+    pub fn get_image_base_dir<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.image_base_dir.value(binary, "ProjectHeader.image_base_dir")
+    }
+    pub fn get_cells<'a>(&self, binary: &'a [u8]) -> Result<&'a [CellEntry], ParseError> {
         self.cell.array(binary, self.num_cells as usize)
     }
     pub fn get_num_cells(&self) -> u16 {
         self.num_cells
     }
-    pub fn get_anime(&self, binary: &[u8]) -> &[Anime] {
+    /// Get the size of each cell map's texture by cell map index, for the cell maps where it can
+    /// be worked out
+    pub fn get_texture_sizes(&self, binary: &[u8]) -> Result<HashMap<u16, Vec2>, ParseError> {
+        let mut cell_maps: HashMap<u16, Vec<&CellEntry>> = HashMap::new();
+        for cell in self.get_cells(binary)? {
+            cell_maps.entry(cell.get_cell_map(binary)?.get_index()).or_default().push(cell);
+        }
+        Ok(cell_maps.into_iter()
+            .filter_map(|(index, cells)| infer_texture_size(&cells).map(|size| (index, size)))
+            .collect())
+    }
+    pub fn get_anime<'a>(&self, binary: &'a [u8]) -> Result<&'a [Anime], ParseError> {
         self.anime_pack_data.array(binary, self.num_anime_packs as usize)
     }
     pub fn get_num_anime(&self) -> u16 {
         self.num_anime_packs
     }
-    pub fn get_effects(&self, binary: &[u8]) -> &[Effect] {
+    /// Find an animation from a "pack/animation" reference, as used by instance parts
+    pub fn find_animation<'a>(&self, binary: &'a [u8], ref_name: &str) -> Result<Option<&'a AnimEntry>, ParseError> {
+        let Some((pack_name, anim_name)) = ref_name.split_once("/") else {
+            return Ok(None);
+        };
+        for pack in self.get_anime(binary)? {
+            if pack.get_name(binary)? != pack_name {
+                continue;
+            }
+            for anim in pack.get_anims(binary, &self.get_revision()?.get_layout())? {
+                if anim.get_name(binary)? == anim_name {
+                    return Ok(Some(anim));
+                }
+            }
+        }
+        Ok(None)
+    }
+    pub fn get_effects<'a>(&self, binary: &'a [u8]) -> Result<&'a [Effect], ParseError> {
         self.effect_file.array(binary, self.num_effect_file_list as usize)
     }
     pub fn get_num_effects(&self) -> u16 {
         self.num_effect_file_list
     }
+    /// Version 11 files don't have a sequence pack table, so they never have any sequence packs
+    pub fn get_sequence_packs<'a>(&self, binary: &'a [u8]) -> Result<&'a [SequencePack], ParseError> {
+        // the header is at the start of the file, so its offsets are also file offsets
+        match self.get_revision()?.get_layout().sequence_packs {
+            Some(offset) => Ptr::<Ptr<SequencePack>>::new(offset as u32).value(binary)?
+                .array(binary, self.num_sequence_packs as usize),
+            None => Ok(&[])
+        }
+    }
+    pub fn get_num_sequence_packs(&self) -> u16 {
+        self.num_sequence_packs
+    }
+}
 
+impl model::Project {
     pub fn to_xml(&self, name: &str, cell_names: &[String],
-        anime_names: &[String], effect_names: &[String]) -> Result<Vec<u8>, Box<dyn Error>> {
+        anime_names: &[String], effect_names: &[String], sequence_names: &[String]) -> Result<Vec<u8>, Box<dyn Error>> {
         let xml_fmt = "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n";
         let mut cursor = Cursor::new(xml_fmt.as_bytes().to_vec());
         cursor.seek(SeekFrom::End(0))?;
-        let mut writer = Writer::new_with_indent(cursor, '\t' as u8, 1);
+        let mut writer = Writer::new_with_indent(cursor, b'\t', 1);
         writer.create_element("SpriteStudioProject")
             .with_attributes([("version", "2.00.00")])
             .write_inner_content(|writer| {
@@ -64,11 +129,12 @@ impl ProjectHeader {
                 writer.create_element("settings")
                     .write_inner_content(|writer| self.settings_to_xml(writer))?;
                 writer.create_element("animeSettings")
-                    .write_inner_content(|writer| to_xml_anime_settings(writer))?;
+                    .write_inner_content(|writer| to_xml_anime_settings(writer, &self.get_anime_settings()))?;
                 tex_pack_settings_to_xml(writer)?;
                 create_name_list("cellmapNames", cell_names, writer)?;
                 create_name_list("animepackNames", anime_names, writer)?;
                 create_name_list("effectFileNames", effect_names, writer)?;
+                create_name_list("sequencepackNames", sequence_names, writer)?;
                 create_blank_element(writer, "lastAnimeFile")?;
                 create_blank_element(writer, "lastAnimeName")?;
                 create_blank_element(writer, "lastPart")?;
@@ -133,10 +199,7 @@ impl ProjectHeader {
             })?;
         writer.create_element("availableAttributes")
             .write_inner_content(|writer| {
-                for attr in ["CELL", "POSX", "POSY", "POSZ", "ROTX", "ROTY", "ROTZ", "SCLX", "SCLY",
-                "LSCX", "LSCY", "ALPH", "LALP", "PRIO", "IFLH", "IFLV", "FLPH", "FLPV", "HIDE",
-                "PCOL", "VCOL", "VERT", "PVTX", "PVTY", "ANCX", "ANCY", "SIZX", "SIZY", "UVTX",
-                "UVTY", "UVRZ", "UVSX", "UVSY", "BNDR", "MASK", "USER", "IPRM", "EFCT"] {
+                for attr in AVAILABLE_ATTRIBUTES {
                     writer.create_element("item")
                         .write_text_content(BytesText::new(attr))?;
                 }
@@ -201,7 +264,7 @@ impl ProjectHeader {
             .write_text_content(BytesText::new("0"))?;
         for i in 'A'..'K' {
             create_blank_element(writer, &format!("attrSelPresetName{}", i))?;
-            writer.create_element(&format!("attrSelPreset{}", i)).write_empty()?;
+            writer.create_element(format!("attrSelPreset{}", i)).write_empty()?;
         }
         Ok(())
     }
@@ -228,7 +291,7 @@ impl ProjectHeader {
         writer.create_element("imageSizeIsPixcel")
             .write_text_content(BytesText::new("1"))?;
         for i in 0..4 {
-            writer.create_element(&format!("imageSizeExpansion{}", i))
+            writer.create_element(format!("imageSizeExpansion{}", i))
                 .write_text_content(BytesText::new("0"))?;
         }
         writer.create_element("webpSettings")
@@ -247,4 +310,241 @@ impl ProjectHeader {
             })?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// A validated SSBP file. Every structure reachable from the project header is bounds checked
+/// when the file is parsed, so the accessors on it only fail if the data is modified afterwards.
+#[derive(Debug)]
+pub struct SsbpFile<'a> {
+    binary: &'a [u8],
+    header: &'a ProjectHeader,
+    revision: Revision
+}
+
+impl<'a> SsbpFile<'a> {
+    pub fn parse(binary: &'a [u8]) -> Result<Self, ParseError> {
+        let header = Ptr::<ProjectHeader>::new(0).value(binary)?;
+        let revision = header.get_revision()?;
+        // later revisions append fields to the header
+        let layout = revision.get_layout();
+        let header_size = layout.header_size;
+        if binary.len() < header_size {
+            return Err(ParseError::OutOfBounds { name: ProjectHeader::NAME, offset: 0, size: header_size });
+        }
+        header.get_image_base_dir(binary)?;
+        for cell in header.get_cells(binary)? {
+            cell.get_name(binary)?;
+            let map = cell.get_cell_map(binary)?;
+            map.get_name(binary)?;
+            map.get_image_path(binary)?;
+        }
+        for anime in header.get_anime(binary)? {
+            anime.get_name(binary)?;
+            let parts = anime.get_parts(binary, &layout)?;
+            for part in parts {
+                part.get_name(binary)?;
+                part.get_ref_name(binary)?;
+                part.get_effect_name(binary)?;
+                part.get_color_label(binary)?;
+            }
+            for anim in anime.get_anims(binary, &layout)? {
+                anim.get_name(binary)?;
+                anim.get_default_data(binary, parts.len(), &layout)?;
+                for label in anim.get_labels(binary)? {
+                    label.get_name(binary)?;
+                }
+                for frame in anim.get_frame_data(binary, anim.get_total_frames() as usize)? {
+                    frame.value(binary)?;
+                }
+                for user in anim.get_user_data(binary, anim.get_total_frames() as usize)? {
+                    user.value(binary)?;
+                }
+                let mesh_uv = anim.get_mesh_uv(binary, parts.len())?;
+                let mesh_index = anim.get_mesh_index(binary, parts.len())?;
+                for (i, part) in parts.iter().enumerate() {
+                    if part.get_type() == PartType::mesh {
+                        mesh_uv[i].get_uvs(binary)?;
+                        mesh_index[i].get_triangles(binary)?;
+                    }
+                }
+            }
+        }
+        for effect in header.get_effects(binary)? {
+            effect.get_name(binary)?;
+            for node in effect.get_nodes(binary)? {
+                for behavior in node.get_behaviors(binary)? {
+                    behavior.value(binary)?;
+                }
+            }
+        }
+        for pack in header.get_sequence_packs(binary)? {
+            pack.get_name(binary)?;
+            for sequence in pack.get_sequences(binary)? {
+                sequence.get_name(binary)?;
+                for item in sequence.get_items(binary)? {
+                    item.get_anime_pack(binary)?;
+                    item.get_animation(binary)?;
+                }
+            }
+        }
+        Ok(Self { binary, header, revision })
+    }
+
+    pub fn get_binary(&self) -> &'a [u8] {
+        self.binary
+    }
+
+    pub fn get_header(&self) -> &'a ProjectHeader {
+        self.header
+    }
+
+    pub fn get_revision(&self) -> Revision {
+        self.revision
+    }
+
+    pub fn get_layout(&self) -> Layout {
+        self.revision.get_layout()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_data::{part_entry_offset, read_u32, write_anime_project, write_empty_header, SsbpWriter, TestFramePart, TestPart};
+    use crate::util::ParseError;
+    use super::SsbpFile;
+
+    fn part() -> TestPart {
+        TestPart { name: "part", parent: -1, position_x: 0. }
+    }
+
+    fn frame() -> Vec<TestFramePart> {
+        vec![TestFramePart { index: 0, low_flag: 0, values: vec![] }]
+    }
+
+    #[test]
+    fn empty_project_parses() {
+        let mut writer = SsbpWriter::default();
+        write_empty_header(&mut writer, 11);
+        let binary = writer.finish();
+        assert!(SsbpFile::parse(binary.bytes()).is_ok());
+    }
+
+    #[test]
+    fn wrong_data_id() {
+        let mut writer = SsbpWriter::default();
+        write_empty_header(&mut writer, 11);
+        writer.patch(0, u32::from_le_bytes(*b"SSAE"));
+        let binary = writer.finish();
+        let err = SsbpFile::parse(binary.bytes()).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidDataId(id) if id == u32::from_le_bytes(*b"SSAE")), "{}", err);
+    }
+
+    #[test]
+    fn unsupported_versions() {
+        for version in [10, 13] {
+            let mut writer = SsbpWriter::default();
+            write_empty_header(&mut writer, version);
+            let binary = writer.finish();
+            let err = SsbpFile::parse(binary.bytes()).unwrap_err();
+            assert!(matches!(err, ParseError::UnsupportedVersion(v) if v == version), "{}", err);
+        }
+        for version in [11, 12] {
+            let mut writer = SsbpWriter::default();
+            write_empty_header(&mut writer, version);
+            let binary = writer.finish();
+            assert_eq!(SsbpFile::parse(binary.bytes()).unwrap().get_revision().get_version(), version);
+        }
+    }
+
+    #[test]
+    fn truncated_header_is_out_of_bounds() {
+        let mut writer = SsbpWriter::default();
+        write_empty_header(&mut writer, 11);
+        let binary = writer.finish();
+        let err = SsbpFile::parse(&binary.bytes()[..0x20]).unwrap_err();
+        assert!(matches!(err, ParseError::OutOfBounds { name: "ProjectHeader", offset: 0, size: 0x24 }), "{}", err);
+    }
+
+    #[test]
+    fn truncated_v12_header_is_out_of_bounds() {
+        let mut writer = SsbpWriter::default();
+        write_empty_header(&mut writer, 12);
+        let binary = writer.finish();
+        // long enough for a version 11 header, but missing the sequence pack pointer
+        let err = SsbpFile::parse(&binary.bytes()[..0x24]).unwrap_err();
+        assert!(matches!(err, ParseError::OutOfBounds { name: "ProjectHeader", offset: 0, size: 0x28 }), "{}", err);
+    }
+
+    #[test]
+    fn truncated_animation_table_is_out_of_bounds() {
+        let binary = write_anime_project(&[part()], &[frame()]);
+        let bytes = binary.bytes();
+        // the animation table is the last thing written
+        let anime = read_u32(bytes, 0x14);
+        let anims = read_u32(bytes, anime + 8);
+        let err = SsbpFile::parse(&bytes[..anims + 8]).unwrap_err();
+        assert!(matches!(err, ParseError::OutOfBounds { name: "AnimEntry", .. }), "{}", err);
+    }
+
+    #[test]
+    fn misaligned_cell_table() {
+        let mut writer = SsbpWriter::default();
+        let [cells, _, _, counts] = write_empty_header(&mut writer, 11);
+        writer.patch_u16(counts, 1);
+        let table = writer.here() + 2;
+        writer.patch(cells, table);
+        writer.bytes(&[0; 0x30]);
+        let binary = writer.finish();
+        let err = SsbpFile::parse(binary.bytes()).unwrap_err();
+        assert!(matches!(err, ParseError::Misaligned { name: "CellEntry", align: 4, .. }), "{}", err);
+    }
+
+    #[test]
+    fn out_of_range_part_type() {
+        let mut binary = write_anime_project(&[part()], &[frame()]);
+        let part = part_entry_offset(binary.bytes(), 0);
+        // the part type follows the name, index and parent
+        binary.bytes_mut()[part + 8..part + 10].copy_from_slice(&99u16.to_le_bytes());
+        let err = SsbpFile::parse(binary.bytes()).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidValue { name: "PartType", value: 99, .. }), "{}", err);
+    }
+
+    #[test]
+    fn non_utf8_and_unterminated_strings() {
+        let mut writer = SsbpWriter::default();
+        write_empty_header(&mut writer, 11);
+        let string = writer.bytes(&[b'a', 0xff, 0]);
+        writer.patch(0xc, string);
+        let binary = writer.finish();
+        let err = SsbpFile::parse(binary.bytes()).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidString { name: "ProjectHeader.image_base_dir", offset }
+            if offset == string as usize), "{}", err);
+
+        let mut writer = SsbpWriter::default();
+        write_empty_header(&mut writer, 11);
+        let string = writer.bytes(b"abcdefgh");
+        writer.patch(0xc, string);
+        let binary = writer.finish();
+        let bytes = &binary.bytes()[..string as usize + 8];
+        assert!(matches!(SsbpFile::parse(bytes), Err(ParseError::InvalidString { .. })));
+    }
+
+    #[test]
+    fn string_past_the_end_is_out_of_bounds() {
+        let mut writer = SsbpWriter::default();
+        write_empty_header(&mut writer, 11);
+        writer.patch(0xc, 0x1000);
+        let binary = writer.finish();
+        let err = SsbpFile::parse(binary.bytes()).unwrap_err();
+        assert!(matches!(err, ParseError::OutOfBounds { name: "ProjectHeader.image_base_dir", offset: 0x1000, .. }), "{}", err);
+    }
+
+    #[test]
+    fn string_errors_name_their_structure() {
+        let mut binary = write_anime_project(&[part()], &[frame()]);
+        let part = part_entry_offset(binary.bytes(), 0);
+        binary.bytes_mut()[part..part + 4].copy_from_slice(&0x10000u32.to_le_bytes());
+        let err = SsbpFile::parse(binary.bytes()).unwrap_err();
+        assert!(matches!(err, ParseError::OutOfBounds { name: "PartEntry.name", offset: 0x10000, .. }), "{}", err);
+    }
+}
@@ -0,0 +1,187 @@
+// Keyframe reduction for converted tracks. The frame stream stores a value for every frame, so a
+// part sliding at a constant speed produces a keyframe on each frame. Reduction removes keyframes
+// that linear interpolation between the keyframes around them already reproduces, using a separate
+// tolerance for each kind of attribute since their units differ.
+
+use crate::cell::InterpolateType;
+use crate::model;
+
+/// Largest difference allowed between a fitted or reduced track and the original keyframes, in
+/// the units shown in SpriteStudio for each attribute
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeyframeTolerances {
+    /// Position, size and bounding radius, in pixels
+    pub position: f32,
+    /// Rotation, in degrees
+    pub rotation: f32,
+    /// Scale, where 1 is the original size
+    pub scale: f32,
+    /// Opacity, in steps of 1/255
+    pub opacity: f32,
+    /// Pivot and UV attributes
+    pub other: f32,
+}
+
+impl Default for KeyframeTolerances {
+    fn default() -> Self {
+        Self {
+            position: 0.01,
+            rotation: 0.01,
+            scale: 0.001,
+            opacity: 0.5,
+            other: 0.001,
+        }
+    }
+}
+
+impl KeyframeTolerances {
+    /// Get the tolerance for an attribute tag, in the attribute's own units
+    pub fn get(&self, tag: &str) -> f32 {
+        match tag {
+            "POSX" | "POSY" | "POSZ" | "SIZX" | "SIZY" | "BNDR" => self.position,
+            "ROTX" | "ROTY" | "ROTZ" | "UVRZ" => self.rotation,
+            "SCLX" | "SCLY" | "LSCX" | "LSCY" => self.scale,
+            "ALPH" | "LALP" => self.opacity / 255.,
+            _ => self.other
+        }
+    }
+}
+
+impl model::Attribute {
+    /// Remove linear keyframes that the keyframes on either side reproduce within a tolerance.
+    /// Keyframes next to a curve are kept, as are tracks that don't hold numbers.
+    pub fn reduce_keyframes(&mut self, tolerance: f32) {
+        if self.keyframes.len() < 3 || !self.keyframes.iter().all(|k| k.value.get_scalar().is_some()) {
+            return;
+        }
+        let sample = |k: &model::Keyframe| (k.frame as f32, k.value.get_scalar().unwrap());
+        let mut kept = vec![0];
+        let mut start = 0;
+        while start + 1 < self.keyframes.len() {
+            let (start_frame, start_value) = sample(&self.keyframes[start]);
+            let mut end = start + 1;
+            // the original track is linear between keyframes, so it's furthest from a new line at
+            // the keyframes themselves
+            while end + 1 < self.keyframes.len()
+                && self.keyframes[start].interpolation == InterpolateType::linear
+                && self.keyframes[end].interpolation == InterpolateType::linear {
+                let (end_frame, end_value) = sample(&self.keyframes[end + 1]);
+                let fits = self.keyframes[start + 1..=end].iter().all(|k| {
+                    let (frame, value) = sample(k);
+                    let t = (frame - start_frame) / (end_frame - start_frame);
+                    (start_value + (end_value - start_value) * t - value).abs() <= tolerance
+                });
+                if !fits {
+                    break;
+                }
+                end += 1;
+            }
+            kept.push(end);
+            start = end;
+        }
+        self.keyframes = kept.into_iter().map(|i| self.keyframes[i].clone()).collect();
+    }
+}
+
+impl model::Animation {
+    pub fn reduce_keyframes(&mut self, tolerances: &KeyframeTolerances) {
+        // setup data only has a single keyframe per attribute
+        if self.is_setup() {
+            return;
+        }
+        for part_anime in &mut self.part_animes {
+            for attribute in &mut part_anime.attributes {
+                let tolerance = tolerances.get(&attribute.tag);
+                attribute.reduce_keyframes(tolerance);
+            }
+        }
+    }
+}
+
+impl model::Project {
+    /// Reduce the keyframes in every animation. See [`model::Attribute::reduce_keyframes`]
+    pub fn reduce_keyframes(&mut self, tolerances: &KeyframeTolerances) {
+        for anime_pack in &mut self.anime_packs {
+            for animation in &mut anime_pack.animations {
+                animation.reduce_keyframes(tolerances);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::anime::AttributeKeyframe;
+    use crate::cell::InterpolateType;
+    use crate::model::{Attribute, Keyframe};
+    use super::KeyframeTolerances;
+
+    fn track(tag: &str, values: &[f32], value: fn(f32) -> AttributeKeyframe) -> Attribute {
+        Attribute {
+            tag: tag.to_string(),
+            keyframes: values.iter().enumerate().map(|(frame, v)| Keyframe {
+                frame, interpolation: InterpolateType::linear, curve: None, value: value(*v)
+            }).collect()
+        }
+    }
+
+    fn frames(attribute: &Attribute) -> Vec<usize> {
+        attribute.keyframes.iter().map(|k| k.frame).collect()
+    }
+
+    /// Value of the reduced track on a frame, using linear interpolation between its keyframes
+    fn evaluate(attribute: &Attribute, frame: usize) -> f32 {
+        let keys = &attribute.keyframes;
+        let next = keys.iter().position(|k| k.frame >= frame).unwrap();
+        let end = &keys[next];
+        if end.frame == frame || next == 0 {
+            return end.value.get_scalar().unwrap();
+        }
+        let start = &keys[next - 1];
+        let t = (frame - start.frame) as f32 / (end.frame - start.frame) as f32;
+        let (a, b) = (start.value.get_scalar().unwrap(), end.value.get_scalar().unwrap());
+        a + (b - a) * t
+    }
+
+    #[test]
+    fn reduced_track_stays_within_tolerance() {
+        let values: Vec<f32> = (0..60).map(|f| (f as f32 * 0.01).sin() * 20.).collect();
+        let mut attribute = track("POSX", &values, AttributeKeyframe::PositionX);
+        let tolerance = KeyframeTolerances::default().get("POSX");
+        attribute.reduce_keyframes(tolerance);
+        assert!(attribute.keyframes.len() < values.len());
+        for (frame, value) in values.iter().enumerate() {
+            assert!((evaluate(&attribute, frame) - value).abs() <= tolerance, "frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn first_last_and_step_keys_are_kept() {
+        let values = [0., 0., 0., 10., 10., 10., 10.];
+        let mut attribute = track("POSY", &values, AttributeKeyframe::PositionY);
+        attribute.reduce_keyframes(KeyframeTolerances::default().get("POSY"));
+        assert_eq!(frames(&attribute), [0, 2, 3, 6]);
+
+        let mut attribute = track("POSY", &values, AttributeKeyframe::PositionY);
+        attribute.keyframes[4].interpolation = InterpolateType::none;
+        attribute.reduce_keyframes(KeyframeTolerances::default().get("POSY"));
+        assert_eq!(frames(&attribute), [0, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn opacity_tolerance_is_in_steps_of_255() {
+        let tolerances = KeyframeTolerances::default();
+        assert_eq!(tolerances.get("ALPH"), tolerances.opacity / 255.);
+        // a third of a step off the line is removed, two steps off is kept
+        for (offset, kept) in [(1. / 3., 2), (2., 5)] {
+            let values = [0., 0.25, 0.5 + offset / 255., 0.75, 1.];
+            let mut attribute = track("ALPH", &values, AttributeKeyframe::Opacity);
+            attribute.reduce_keyframes(tolerances.get("ALPH"));
+            assert_eq!(attribute.keyframes.len(), kept);
+            for (frame, value) in values.iter().enumerate() {
+                assert!((evaluate(&attribute, frame) - value).abs() <= tolerances.get("ALPH"));
+            }
+        }
+    }
+}
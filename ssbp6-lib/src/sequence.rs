@@ -0,0 +1,204 @@
+// .ssqe XML format:
+//
+// <?xml version="1.0" encoding="utf-8" standalone="yes"?>
+// <SpriteStudioSequencePack version="2.00.00">
+//  <name>pack.name</name>
+//  <sequenceList>
+//      <sequence>
+//          <name>sequence.name</name>
+//          <index>sequence.index</index>
+//          <type>sequence.type</type>
+//          <list>
+//              <value>
+//                  <refAnimePack>item.animePack</refAnimePack>
+//                  <refAnime>item.animation</refAnime>
+//                  <repeatCount>item.repeatCount</repeatCount>
+//              </value>
+//          </list>
+//      </sequence>
+//  </sequenceList>
+// </SpriteStudioSequencePack>
+
+use std::error::Error;
+use std::io::{Cursor, Seek, SeekFrom, Write};
+use quick_xml::events::BytesText;
+use quick_xml::Writer;
+use crate::model;
+use crate::util::{check_enum, create_blank_element, BinaryStruct, ParseError, Ptr, StringPtr};
+
+/// What's shown after the last item of a sequence finishes
+#[repr(u16)]
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[allow(non_camel_case_types)]
+pub enum SequenceType {
+    last,   //< Stop on the last frame of the last item
+    keep,   //< Keep the last item playing
+    top,    //< Loop back to the first item
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct SequenceItem {
+    anime_pack: StringPtr,
+    animation: StringPtr,
+    repeat_count: i16
+}
+
+unsafe impl BinaryStruct for SequenceItem {
+    const NAME: &'static str = "SequenceItem";
+}
+
+impl SequenceItem {
+    pub fn get_anime_pack<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.anime_pack.value(binary, "SequenceItem.anime_pack")
+    }
+    pub fn get_animation<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.animation.value(binary, "SequenceItem.animation")
+    }
+    pub fn get_repeat_count(&self) -> i16 {
+        self.repeat_count
+    }
+
+    pub fn to_model(&self, binary: &[u8]) -> Result<model::SequenceItem, ParseError> {
+        Ok(model::SequenceItem {
+            anime_pack: self.get_anime_pack(binary)?.to_string(),
+            animation: self.get_animation(binary)?.to_string(),
+            repeat_count: self.repeat_count,
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct Sequence {
+    name: StringPtr,
+    list: Ptr<SequenceItem>,
+    num_items: i16,
+    index: i16,
+    _type: SequenceType
+}
+
+unsafe impl BinaryStruct for Sequence {
+    const NAME: &'static str = "Sequence";
+    fn validate(binary: &[u8], offset: usize) -> Result<(), ParseError> {
+        check_enum("SequenceType", binary, offset + std::mem::offset_of!(Sequence, _type), SequenceType::top as u16)
+    }
+}
+
+impl Sequence {
+    pub fn get_name<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.name.value(binary, "Sequence.name")
+    }
+    pub fn get_items<'a>(&self, binary: &'a [u8]) -> Result<&'a [SequenceItem], ParseError> {
+        self.list.array(binary, self.num_items.max(0) as usize)
+    }
+    pub fn get_index(&self) -> i16 {
+        self.index
+    }
+    pub fn get_type(&self) -> SequenceType {
+        self._type
+    }
+
+    pub fn to_model(&self, binary: &[u8]) -> Result<model::Sequence, ParseError> {
+        Ok(model::Sequence {
+            name: self.get_name(binary)?.to_string(),
+            index: self.index,
+            sequence_type: self._type,
+            items: self.get_items(binary)?.iter().map(|i| i.to_model(binary)).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// A sequence pack stored in an SSBP. The table is only in version 12 files, see
+/// [`ProjectHeader::get_sequence_packs`](crate::project::ProjectHeader::get_sequence_packs).
+#[repr(C)]
+#[derive(Debug)]
+pub struct SequencePack {
+    name: StringPtr,
+    list: Ptr<Sequence>,
+    num_sequences: i16
+}
+
+unsafe impl BinaryStruct for SequencePack {
+    const NAME: &'static str = "SequencePack";
+}
+
+unsafe impl BinaryStruct for Ptr<SequencePack> {
+    const NAME: &'static str = "Ptr<SequencePack>";
+}
+
+impl SequencePack {
+    pub fn get_name<'a>(&self, binary: &'a [u8]) -> Result<&'a str, ParseError> {
+        self.name.value(binary, "SequencePack.name")
+    }
+    pub fn get_sequences<'a>(&self, binary: &'a [u8]) -> Result<&'a [Sequence], ParseError> {
+        self.list.array(binary, self.num_sequences.max(0) as usize)
+    }
+
+    pub fn to_model(&self, binary: &[u8]) -> Result<model::SequencePack, ParseError> {
+        Ok(model::SequencePack {
+            name: self.get_name(binary)?.to_string(),
+            sequences: self.get_sequences(binary)?.iter().map(|s| s.to_model(binary)).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl model::SequencePack {
+    pub fn to_xml(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let xml_fmt = "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n";
+        let mut cursor = Cursor::new(xml_fmt.as_bytes().to_vec());
+        cursor.seek(SeekFrom::End(0))?;
+        let mut writer = Writer::new_with_indent(cursor, b'\t', 1);
+        writer.create_element("SpriteStudioSequencePack")
+            .with_attributes([("version", "2.00.00")])
+            .write_inner_content(|writer| self.to_xml_body(writer))?;
+        Ok(writer.into_inner().into_inner())
+    }
+
+    pub(crate) fn to_xml_body<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
+        writer.create_element("name")
+            .write_text_content(BytesText::new(&self.name))?;
+        create_blank_element(writer, "exportPath")?;
+        writer.create_element("sequenceList")
+            .write_inner_content(|writer| {
+                for sequence in &self.sequences {
+                    writer.create_element("sequence")
+                        .write_inner_content(|writer| sequence.to_xml(writer))?;
+                }
+                Ok(())
+            })?;
+        Ok(())
+    }
+}
+
+impl model::Sequence {
+    pub fn to_xml<W: Write + Seek>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
+        writer.create_element("name")
+            .write_text_content(BytesText::new(&self.name))?;
+        writer.create_element("index")
+            .write_text_content(BytesText::new(&format!("{}", self.index)))?;
+        writer.create_element("type")
+            .write_text_content(BytesText::new(&format!("{:?}", self.sequence_type)))?;
+        let list = writer.create_element("list");
+        match self.items.len() {
+            0 => list.write_empty(),
+            _ => list.write_inner_content(|writer| {
+                for item in &self.items {
+                    writer.create_element("value")
+                        .write_inner_content(|writer| {
+                            writer.create_element("refAnimePack")
+                                .write_text_content(BytesText::new(&item.anime_pack))?;
+                            writer.create_element("refAnime")
+                                .write_text_content(BytesText::new(&item.animation))?;
+                            writer.create_element("repeatCount")
+                                .write_text_content(BytesText::new(&format!("{}", item.repeat_count)))?;
+                            Ok(())
+                        })?;
+                }
+                Ok(())
+            })
+        }?;
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+//! Helpers for building small SSBP files in tests
+
+use std::collections::HashMap;
+use crate::anime::{PartEntry, PartFrame};
+use crate::model::{AnimePack, Warning};
+use crate::project::SsbpFile;
+
+/// Writes little endian data with every value aligned to its size, the same way the converter
+/// lays out an SSBP
+#[derive(Debug, Default)]
+pub(crate) struct SsbpWriter {
+    data: Vec<u8>,
+}
+
+impl SsbpWriter {
+    pub(crate) fn here(&self) -> u32 {
+        self.data.len() as u32
+    }
+    pub(crate) fn align(&mut self, align: usize) {
+        while !self.data.len().is_multiple_of(align) {
+            self.data.push(0);
+        }
+    }
+    pub(crate) fn u16(&mut self, value: u16) -> u32 {
+        self.align(2);
+        let offset = self.here();
+        self.data.extend_from_slice(&value.to_le_bytes());
+        offset
+    }
+    pub(crate) fn u32(&mut self, value: u32) -> u32 {
+        self.align(4);
+        let offset = self.here();
+        self.data.extend_from_slice(&value.to_le_bytes());
+        offset
+    }
+    pub(crate) fn f32(&mut self, value: f32) -> u32 {
+        self.u32(value.to_bits())
+    }
+    /// Write bytes without aligning them, as in the frame stream
+    pub(crate) fn bytes(&mut self, value: &[u8]) -> u32 {
+        let offset = self.here();
+        self.data.extend_from_slice(value);
+        offset
+    }
+    /// Write a null terminated string, returning its offset
+    pub(crate) fn string(&mut self, value: &str) -> u32 {
+        let offset = self.here();
+        self.data.extend_from_slice(value.as_bytes());
+        self.data.push(0);
+        offset
+    }
+    /// Overwrite a u32 that was written earlier, for filling in pointers
+    pub(crate) fn patch(&mut self, offset: u32, value: u32) {
+        let offset = offset as usize;
+        self.data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+    pub(crate) fn patch_u16(&mut self, offset: u32, value: u16) {
+        let offset = offset as usize;
+        self.data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+    pub(crate) fn finish(mut self) -> AlignedBinary {
+        self.align(8);
+        AlignedBinary(self.data.chunks(8).map(|c| u64::from_le_bytes(c.try_into().unwrap())).collect())
+    }
+}
+
+/// SSBP data in a buffer that's aligned for every structure in the file
+pub(crate) struct AlignedBinary(Vec<u64>);
+
+impl AlignedBinary {
+    pub(crate) fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.0.as_ptr() as *const u8, self.0.len() * 8) }
+    }
+    pub(crate) fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.0.as_mut_ptr() as *mut u8, self.0.len() * 8) }
+    }
+}
+
+/// Write a project header with nothing in it, returning the offsets of the cell, anime pack and
+/// effect pointers and the offset of the counts (cells, anime packs, effects, sequence packs).
+/// Version 12 headers have a sequence pack pointer after the counts.
+pub(crate) fn write_empty_header(writer: &mut SsbpWriter, version: u32) -> [u32; 4] {
+    writer.u32(crate::revision::SSBP_DATA_ID);
+    writer.u32(version);
+    writer.u32(0);
+    let image_base_dir = writer.u32(0);
+    let cells = writer.u32(0);
+    let anime = writer.u32(0);
+    let effects = writer.u32(0);
+    let counts = writer.u16(0);
+    writer.u16(0);
+    writer.u16(0);
+    writer.u16(0);
+    if version >= 12 {
+        writer.u32(0);
+    }
+    let empty = writer.string("");
+    writer.patch(image_base_dir, empty);
+    [cells, anime, effects, counts]
+}
+
+/// A null part and its initial X position
+pub(crate) struct TestPart {
+    pub(crate) name: &'static str,
+    pub(crate) parent: i16,
+    pub(crate) position_x: f32,
+}
+
+/// One part's entry in a frame: the index it's stored with, its low flags and the values that
+/// follow
+pub(crate) struct TestFramePart {
+    pub(crate) index: u16,
+    pub(crate) low_flag: u32,
+    pub(crate) values: Vec<f32>,
+}
+
+/// Write a project with a single anime pack holding one animation called "anim"
+pub(crate) fn write_anime_project(parts: &[TestPart], frames: &[Vec<TestFramePart>]) -> AlignedBinary {
+    let mut writer = SsbpWriter::default();
+    let [_, anime_ptr, _, counts] = write_empty_header(&mut writer, 11);
+    writer.patch_u16(counts + 2, 1);
+    let empty = writer.string("");
+    let pack_name = writer.string("pack");
+    let anim_name = writer.string("anim");
+    let part_names: Vec<u32> = parts.iter().map(|p| writer.string(p.name)).collect();
+
+    let anime = writer.u32(pack_name);
+    writer.patch(anime_ptr, anime);
+    let parts_ptr = writer.u32(0);
+    let anims_ptr = writer.u32(0);
+    writer.u16(parts.len() as u16);
+    writer.u16(1);
+
+    writer.align(4);
+    writer.patch(parts_ptr, writer.here());
+    for (i, (part, name)) in parts.iter().zip(&part_names).enumerate() {
+        writer.u32(*name);
+        writer.u16(i as u16);
+        writer.u16(part.parent as u16);
+        // null part, default bounds and blending
+        writer.u16(0);
+        writer.u16(0);
+        writer.u16(0);
+        for _ in 0..3 {
+            writer.u32(empty);
+        }
+        writer.u16(0);
+        writer.align(4);
+    }
+
+    let default_data = writer.here();
+    for (i, part) in parts.iter().enumerate() {
+        writer.u16(i as u16);
+        writer.u16(0);
+        writer.u32(0);
+        writer.u32(0);
+        // priority, cell index, opacity, local opacity, mask, reserved
+        for value in [0, 0xffff, 255, 255, 0, 0] {
+            writer.u16(value);
+        }
+        // position, pivot, rotation, scale, local scale, size, uv move, uv rotation, uv scale,
+        // bounding radius
+        for value in [part.position_x, 0., 0., 0., 0., 0., 0., 0., 1., 1., 1., 1., 0., 0., 0., 0., 0., 1., 1., 0.] {
+            writer.f32(value);
+        }
+        // instance and effect settings
+        for _ in 0..10 {
+            writer.u32(0);
+        }
+    }
+
+    let mut frame_offsets = vec![];
+    for frame in frames {
+        frame_offsets.push(writer.here());
+        for part in frame {
+            writer.bytes(&part.index.to_le_bytes());
+            writer.bytes(&part.low_flag.to_le_bytes());
+            writer.bytes(&0u32.to_le_bytes());
+            for value in &part.values {
+                writer.bytes(&value.to_le_bytes());
+            }
+        }
+    }
+    writer.align(4);
+    let frame_data = writer.here();
+    for offset in frame_offsets {
+        writer.u32(offset);
+    }
+    // no meshes, so every part's mesh data is empty
+    let mesh_data = writer.here();
+    for _ in parts {
+        writer.u32(0);
+    }
+
+    writer.patch(anims_ptr, writer.here());
+    for ptr in [anim_name, default_data, frame_data, 0, 0, mesh_data, mesh_data] {
+        writer.u32(ptr);
+    }
+    // start, end and total frames, fps, labels, canvas size
+    for value in [0, frames.len() as u16 - 1, frames.len() as u16, 30, 0, 320, 320] {
+        writer.u16(value);
+    }
+    writer.f32(0.);
+    writer.f32(0.);
+    writer.finish()
+}
+
+/// Read a pointer or count from a written file
+pub(crate) fn read_u32(binary: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes(binary[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+/// Offset of a part's PartEntry in a file from [`write_anime_project`], for patching it
+pub(crate) fn part_entry_offset(binary: &[u8], index: usize) -> usize {
+    let anime = read_u32(binary, 0x14);
+    read_u32(binary, anime + 4) + index * size_of::<PartEntry>()
+}
+
+/// Parse a file from [`write_anime_project`] and convert its anime pack
+pub(crate) fn anime_pack_model(binary: &AlignedBinary, warnings: &mut Vec<Warning>) -> AnimePack {
+    let file = SsbpFile::parse(binary.bytes()).unwrap();
+    let anime = &file.get_header().get_anime(file.get_binary()).unwrap()[0];
+    anime.to_model(&file, &HashMap::new(), &HashMap::new(), warnings).unwrap()
+}
+
+/// Parse a file from [`write_anime_project`] and decode every part of each frame of its
+/// animation, up to and including the first error
+pub(crate) fn part_frames(binary: &AlignedBinary, num_parts: usize) -> Vec<std::io::Result<PartFrame>> {
+    let file = SsbpFile::parse(binary.bytes()).unwrap();
+    let binary = file.get_binary();
+    let anime = &file.get_header().get_anime(binary).unwrap()[0];
+    let anim = &anime.get_anims(binary, &file.get_layout()).unwrap()[0];
+    anim.get_part_frames(binary, num_parts, &file.get_layout()).unwrap().collect()
+}
+
+/// An effect node, its parent's index and the raw bytes of each of its behaviors
+pub(crate) struct TestNode {
+    pub(crate) parent: i16,
+    pub(crate) node_type: crate::effect::EffectNodeType,
+    pub(crate) behaviors: Vec<Vec<u8>>,
+}
+
+/// Write a project with a single effect called "effect". Behaviors are written last, so the last
+/// one ends at the end of the file.
+pub(crate) fn write_effect_project(nodes: &[TestNode]) -> AlignedBinary {
+    let mut writer = SsbpWriter::default();
+    let [_, _, effect_ptr, counts] = write_empty_header(&mut writer, 11);
+    writer.patch_u16(counts + 4, 1);
+    let name = writer.string("effect");
+
+    let effect = writer.u32(name);
+    writer.patch(effect_ptr, effect);
+    // fps, random seed lock and seed, layout scale
+    for value in [30, 0, 0, 100, 100, nodes.len() as u16] {
+        writer.u16(value);
+    }
+    let nodes_ptr = writer.u32(0);
+
+    writer.align(4);
+    writer.patch(nodes_ptr, writer.here());
+    let mut behavior_ptrs = vec![];
+    for (i, node) in nodes.iter().enumerate() {
+        writer.u16(i as u16);
+        writer.u16(node.parent as u16);
+        writer.u16(node.node_type as u16);
+        // no cell, mix blending
+        writer.u16(-1i16 as u16);
+        writer.u16(0);
+        writer.u16(node.behaviors.len() as u16);
+        behavior_ptrs.push(writer.u32(0));
+    }
+    let mut tables = vec![];
+    for (node, ptr) in nodes.iter().zip(behavior_ptrs) {
+        writer.align(4);
+        writer.patch(ptr, writer.here());
+        tables.extend(node.behaviors.iter().map(|_| writer.u32(0)));
+    }
+    for (behavior, ptr) in nodes.iter().flat_map(|n| &n.behaviors).zip(tables) {
+        writer.align(4);
+        writer.patch(ptr, writer.here());
+        writer.bytes(behavior);
+    }
+    writer.finish()
+}
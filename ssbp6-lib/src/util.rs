@@ -1,13 +1,120 @@
+use std::error::Error;
 use std::ffi::CStr;
+use std::fmt::{Debug, Display, Formatter};
 use std::io::{Seek, Write};
 use std::marker::PhantomData;
+use std::ops::Index;
+use glam::{UVec3, Vec2, Vec3};
 use quick_xml::events::BytesText;
 use quick_xml::Writer;
-use crate::anime::AttributeKeyframe;
+use crate::model;
 
-#[allow(dead_code)]
-pub(crate) unsafe fn from_bytes<T>(s: &[u8], o: usize) -> &T {
-    unsafe { &*(s.as_ptr().add(o) as *const T) }
+#[derive(Debug)]
+pub enum ParseError {
+    OutOfBounds { name: &'static str, offset: usize, size: usize },
+    Misaligned { name: &'static str, offset: usize, align: usize },
+    InvalidValue { name: &'static str, offset: usize, value: usize },
+    InvalidString { name: &'static str, offset: usize },
+    InvalidDataId(u32),
+    UnsupportedVersion(u32),
+}
+
+impl Error for ParseError {}
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OutOfBounds { name, offset, size } =>
+                write!(f, "{} at 0x{:x} (0x{:x} bytes) is out of bounds", name, offset, size),
+            Self::Misaligned { name, offset, align } =>
+                write!(f, "{} at 0x{:x} isn't aligned to {} bytes", name, offset, align),
+            Self::InvalidValue { name, offset, value } =>
+                write!(f, "{} at 0x{:x} has invalid value {}", name, offset, value),
+            Self::InvalidString { name, offset } =>
+                write!(f, "{} at 0x{:x} isn't a null terminated UTF-8 string", name, offset),
+            Self::InvalidDataId(id) => write!(f, "invalid SSBP data ID 0x{:x}", id),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported SSBP version {}", v),
+        }
+    }
+}
+
+impl From<ParseError> for std::io::Error {
+    fn from(value: ParseError) -> Self {
+        std::io::Error::other(value)
+    }
+}
+
+/// A structure that's stored inside of an SSBP file and can be borrowed directly from it.
+///
+/// # Safety
+///
+/// Implementors must be plain data: every bit pattern has to be a valid value once
+/// [`BinaryStruct::validate`] has accepted it. Fields with restricted values (such as enums)
+/// must be checked in `validate`.
+pub unsafe trait BinaryStruct: Sized {
+    const NAME: &'static str;
+    /// Check the contents of the structure at `offset`. Bounds and alignment have already been
+    /// checked by the caller.
+    fn validate(_binary: &[u8], _offset: usize) -> Result<(), ParseError> {
+        Ok(())
+    }
+}
+
+unsafe impl BinaryStruct for u16 {
+    const NAME: &'static str = "u16";
+}
+
+unsafe impl BinaryStruct for i16 {
+    const NAME: &'static str = "i16";
+}
+
+unsafe impl BinaryStruct for u32 {
+    const NAME: &'static str = "u32";
+}
+
+unsafe impl BinaryStruct for f32 {
+    const NAME: &'static str = "f32";
+}
+
+unsafe impl BinaryStruct for Vec2 {
+    const NAME: &'static str = "Vec2";
+}
+
+unsafe impl BinaryStruct for Vec3 {
+    const NAME: &'static str = "Vec3";
+}
+
+unsafe impl BinaryStruct for UVec3 {
+    const NAME: &'static str = "UVec3";
+}
+
+/// Ensure that `count` instances of T starting at `offset` fit inside the file.
+pub(crate) fn check_range<T: BinaryStruct>(binary: &[u8], offset: usize, count: usize) -> Result<(), ParseError> {
+    let size = size_of::<T>().checked_mul(count);
+    match size.and_then(|s| s.checked_add(offset)) {
+        Some(end) if end <= binary.len() => Ok(()),
+        _ => Err(ParseError::OutOfBounds { name: T::NAME, offset, size: size.unwrap_or(usize::MAX) })
+    }
+}
+
+/// Ensure that `count` instances of T starting at `offset` fit inside the file and that the
+/// first one is correctly aligned to be borrowed.
+pub(crate) fn check_bounds<T: BinaryStruct>(binary: &[u8], offset: usize, count: usize) -> Result<(), ParseError> {
+    check_range::<T>(binary, offset, count)?;
+    if !(binary.as_ptr() as usize + offset).is_multiple_of(align_of::<T>()) {
+        return Err(ParseError::Misaligned { name: T::NAME, offset, align: align_of::<T>() });
+    }
+    Ok(())
+}
+
+/// Check that the u16 discriminant at `offset` is within 0..=max
+pub(crate) fn check_enum(name: &'static str, binary: &[u8], offset: usize, max: u16) -> Result<(), ParseError> {
+    let value = binary.get(offset..offset + 2)
+        .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+        .ok_or(ParseError::OutOfBounds { name, offset, size: 2 })?;
+    match value <= max {
+        true => Ok(()),
+        false => Err(ParseError::InvalidValue { name, offset, value: value as usize })
+    }
 }
 
 #[repr(C)]
@@ -18,22 +125,149 @@ pub struct Ptr<T> {
 }
 
 impl<T> Ptr<T> {
-    pub fn value(&self, s: &[u8]) -> &T {
-        unsafe { &*(s.as_ptr().add(self.offset as usize) as *const T) }
+    pub(crate) fn new(offset: u32) -> Self {
+        Self { offset, _type_marker: PhantomData }
+    }
+
+    pub fn get_offset(&self) -> usize {
+        self.offset as usize
     }
 
-    pub fn array(&self, s: &[u8], c: usize) -> &[T] {
-        unsafe { std::slice::from_raw_parts(s.as_ptr().add(self.offset as usize) as _, c) }
+    /// Point to a different type at the same offset
+    pub(crate) fn cast<U>(&self) -> Ptr<U> {
+        Ptr::new(self.offset)
+    }
+}
+
+impl<T: BinaryStruct> Ptr<T> {
+    pub fn value<'a>(&self, s: &'a [u8]) -> Result<&'a T, ParseError> {
+        let offset = self.offset as usize;
+        check_bounds::<T>(s, offset, 1)?;
+        T::validate(s, offset)?;
+        Ok(unsafe { &*(s.as_ptr().add(offset) as *const T) })
+    }
+
+    pub fn array<'a>(&self, s: &'a [u8], c: usize) -> Result<&'a [T], ParseError> {
+        let offset = self.offset as usize;
+        check_bounds::<T>(s, offset, c)?;
+        for i in 0..c {
+            T::validate(s, offset + i * size_of::<T>())?;
+        }
+        Ok(unsafe { std::slice::from_raw_parts(s.as_ptr().add(offset) as _, c) })
+    }
+}
+
+impl<T: BinaryStruct> Ptr<T> {
+    /// Get an array of `c` entries which are `stride` bytes apart. Used for structures whose
+    /// size depends on the file's [`Revision`](crate::revision::Revision).
+    pub fn table<'a>(&self, s: &'a [u8], c: usize, stride: usize) -> Result<Table<'a, T>, ParseError> {
+        let offset = self.offset as usize;
+        if stride < size_of::<T>() || !stride.is_multiple_of(align_of::<T>()) {
+            return Err(ParseError::InvalidValue { name: T::NAME, offset, value: stride });
+        }
+        if c > 0 {
+            check_bounds::<T>(s, offset, 1)?;
+            let last = stride.checked_mul(c - 1).and_then(|o| o.checked_add(offset))
+                .ok_or(ParseError::OutOfBounds { name: T::NAME, offset, size: usize::MAX })?;
+            check_range::<T>(s, last, 1)?;
+        }
+        for i in 0..c {
+            T::validate(s, offset + i * stride)?;
+        }
+        Ok(Table { binary: s, offset, stride, len: c, _type_marker: PhantomData })
+    }
+}
+
+/// A validated array of structures that are `stride` bytes apart in the file.
+#[derive(Debug)]
+pub struct Table<'a, T> {
+    binary: &'a [u8],
+    offset: usize,
+    stride: usize,
+    len: usize,
+    _type_marker: PhantomData<&'a T>
+}
+
+impl<'a, T> Clone for Table<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for Table<'a, T> {}
+
+impl<'a, T: BinaryStruct> Table<'a, T> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn get(&self, index: usize) -> Option<&'a T> {
+        match index < self.len {
+            true => Some(unsafe { &*(self.binary.as_ptr().add(self.offset + index * self.stride) as *const T) }),
+            false => None
+        }
+    }
+    pub fn first(&self) -> Option<&'a T> {
+        self.get(0)
+    }
+    pub fn iter(&self) -> TableIter<'a, T> {
+        TableIter { table: *self, index: 0 }
+    }
+}
+
+impl<'a, T: BinaryStruct> Index<usize> for Table<'a, T> {
+    type Output = T;
+    fn index(&self, index: usize) -> &Self::Output {
+        self.get(index).unwrap()
+    }
+}
+
+impl<'a, T: BinaryStruct> IntoIterator for Table<'a, T> {
+    type Item = &'a T;
+    type IntoIter = TableIter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T: BinaryStruct> IntoIterator for &Table<'a, T> {
+    type Item = &'a T;
+    type IntoIter = TableIter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
     }
 }
 
-#[repr(transparent)]
 #[derive(Debug)]
+pub struct TableIter<'a, T> {
+    table: Table<'a, T>,
+    index: usize
+}
+
+impl<'a, T: BinaryStruct> Iterator for TableIter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        let value = self.table.get(self.index)?;
+        self.index += 1;
+        Some(value)
+    }
+}
+
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy)]
 pub struct StringPtr(u32);
 
 impl StringPtr {
-    pub fn value(&self, s: &[u8]) -> &str {
-        unsafe { CStr::from_ptr(s.as_ptr().add(self.0 as usize) as _).to_str().unwrap() }
+    /// `name` is the structure and field that holds the string, for error messages
+    pub fn value<'a>(&self, s: &'a [u8], name: &'static str) -> Result<&'a str, ParseError> {
+        let offset = self.0 as usize;
+        let bytes = s.get(offset..)
+            .ok_or(ParseError::OutOfBounds { name, offset, size: 1 })?;
+        CStr::from_bytes_until_nul(bytes).ok()
+            .and_then(|c| c.to_str().ok())
+            .ok_or(ParseError::InvalidString { name, offset })
     }
 }
 
@@ -61,17 +295,18 @@ where W: Write + Seek
     Ok(())
 }
 
-pub(crate) fn to_xml_anime_settings<W: Write + Seek>(writer: &mut Writer<W>) -> std::io::Result<()> {
+pub(crate) fn to_xml_anime_settings<W: Write + Seek>(writer: &mut Writer<W>,
+    settings: &model::AnimeSettings) -> std::io::Result<()> {
     writer.create_element("fps")
-        .write_text_content(BytesText::new("30"))?;
+        .write_text_content(BytesText::new(&format!("{}", settings.fps)))?;
     writer.create_element("frameCount")
-        .write_text_content(BytesText::new("11"))?;
+        .write_text_content(BytesText::new(&format!("{}", settings.total_frames)))?;
     writer.create_element("sortMode")
         .write_text_content(BytesText::new("prio"))?;
     writer.create_element("canvasSize")
-        .write_text_content(BytesText::new("320 320"))?;
+        .write_text_content(BytesText::new(&format!("{} {}", settings.canvas_size.x, settings.canvas_size.y)))?;
     writer.create_element("pivot")
-        .write_text_content(BytesText::new("0 0"))?;
+        .write_text_content(BytesText::new(&format!("{} {}", settings.canvas_pivot.x, settings.canvas_pivot.y)))?;
     writer.create_element("bgColor")
         .write_text_content(BytesText::new("FF323232"))?;
     writer.create_element("gridSize")
@@ -81,9 +316,9 @@ pub(crate) fn to_xml_anime_settings<W: Write + Seek>(writer: &mut Writer<W>) ->
     writer.create_element("ik_depth")
         .write_text_content(BytesText::new("3"))?;
     writer.create_element("startFrame")
-        .write_text_content(BytesText::new("0"))?;
+        .write_text_content(BytesText::new(&format!("{}", settings.start_frame)))?;
     writer.create_element("endFrame")
-        .write_text_content(BytesText::new("10"))?;
+        .write_text_content(BytesText::new(&format!("{}", settings.end_frame)))?;
     writer.create_element("bgSettings")
         .write_inner_content(|writer| to_xml_anime_bg_settings(writer))?;
     writer.create_element("outStartNum")
@@ -92,7 +327,7 @@ pub(crate) fn to_xml_anime_settings<W: Write + Seek>(writer: &mut Writer<W>) ->
 }
 
 pub(crate) fn to_xml_anime_bg_settings<W: Write + Seek>(writer: &mut Writer<W>) -> std::io::Result<()> {
-    for i in 0..2 {
+    for _ in 0..2 {
         writer.create_element("value")
             .write_inner_content(|writer| {
                 create_blank_element(writer, "imagePath")?;
@@ -108,4 +343,28 @@ pub(crate) fn to_xml_anime_bg_settings<W: Write + Seek>(writer: &mut Writer<W>)
             })?;
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_data::SsbpWriter;
+    use super::{ParseError, Ptr};
+
+    #[test]
+    fn table_entries_are_stride_apart() {
+        let mut writer = SsbpWriter::default();
+        // each entry has a u32 appended that a smaller structure doesn't read
+        for value in [1, 2, 3] {
+            writer.u32(value);
+            writer.u32(0xaaaaaaaa);
+        }
+        let binary = writer.finish();
+        let table = Ptr::<u32>::new(0).table(binary.bytes(), 3, 8).unwrap();
+        assert_eq!(table.iter().copied().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!((table.len(), table[2], table.get(3)), (3, 3, None));
+        assert!(matches!(Ptr::<u32>::new(0).table(binary.bytes(), 3, 2),
+            Err(ParseError::InvalidValue { name: "u32", offset: 0, value: 2 })));
+        assert!(matches!(Ptr::<u32>::new(0).table(binary.bytes(), 4, 8),
+            Err(ParseError::OutOfBounds { name: "u32", offset: 24, .. })));
+    }
+}